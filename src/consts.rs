@@ -27,8 +27,10 @@ pub struct Mask {
 }
 
 // Types of move to be generated
-pub const ALL: bool = true;
-pub const CAPTURES: bool = false;
+pub const ALL: u8 = 0;
+pub const CAPTURES: u8 = 1;
+pub const QUIETS: u8 = 2;
+pub const CHECKS: u8 = 3;
 
 /// The type of bound determined by the hash entry when it was searched.
 pub struct Bound;
@@ -120,7 +122,7 @@ pub const DIAGS: [u64; 15] = [
 // masks for hyperbola quintessence rook and bishop attacks
 pub const BMASKS: [Mask; 64] = init!(idx, Mask { bit: 0, right: 0, left: 0, file: 0 }, {
     let bit = 1 << idx;
-    Mask { bit, right: bit ^ DIAGS[(7 + (idx & 7) - (idx >> 3))], left: bit ^ DIAGS[((idx & 7) + (idx >> 3))].swap_bytes(), file: bit.swap_bytes() }
+    Mask { bit, right: bit ^ DIAGS[7 + (idx & 7) - (idx >> 3)], left: bit ^ DIAGS[(idx & 7) + (idx >> 3)].swap_bytes(), file: bit.swap_bytes() }
 });
 
 pub const RMASKS: [Mask; 64] = init!(idx, Mask { bit: 0, right: 0, left: 0, file: 0 }, {
@@ -137,15 +139,86 @@ pub const MAX_PLY: i8 = 96;
 pub const KILLERS_PER_PLY: usize = 3;
 pub const MAX: i16 = 30000;
 pub const MATE_THRESHOLD: i16 = MAX - u8::MAX as i16;
+pub const TB_WIN: i16 = MATE_THRESHOLD - MAX_PLY as i16 - 1;
+pub const TB_THRESHOLD: i16 = TB_WIN - MAX_PLY as i16;
+pub const KNOWN_WIN: i16 = 10000;
 pub const SIDE_FACTOR: [i16; 2] = [1, -1];
 pub const PHASE_VALS: [i16; 7] = [0, 1, 1, 2, 4, 0, 0];
 pub const TPHASE: i32 = 24;
 
+// pawn structure, indexed by relative rank where needed
+pub const PAWN_HASH_SIZE: usize = 1 << 16;
+pub const DOUBLED: S = S(-8, -22);
+pub const ISOLATED: S = S(-12, -10);
+pub const BACKWARD: S = S(-6, -10);
+pub const PASSED: [S; 8] = [S(0, 0), S(2, 8), S(4, 12), S(8, 26), S(24, 48), S(48, 90), S(80, 140), S(0, 0)];
+pub const CONNECTED: [S; 8] = [S(0, 0), S(4, 0), S(8, 2), S(12, 8), S(20, 16), S(36, 32), S(64, 64), S(0, 0)];
+pub const PASSER_OWN_KING: S = S(0, -4);
+pub const PASSER_OPP_KING: S = S(0, 8);
+pub const UNSTOPPABLE: S = S(0, 400);
+
+// piece activity, mobility indexed by number of safe squares attacked
+pub const KNIGHT_MOBILITY: [S; 9] = [S(-31, -40), S(-26, -28), S(-6, -15), S(-2, -7), S(2, 4), S(6, 8), S(11, 12), S(14, 14), S(16, 16)];
+pub const BISHOP_MOBILITY: [S; 14] = [
+    S(-24, -30), S(-10, -12), S(8, -2), S(13, 6), S(19, 12), S(25, 21), S(28, 27),
+    S(31, 28), S(32, 32), S(34, 36), S(40, 39), S(40, 43), S(45, 44), S(49, 48),
+];
+pub const ROOK_MOBILITY: [S; 15] = [
+    S(-29, -38), S(-14, -9), S(-8, 14), S(-5, 27), S(-2, 34), S(-1, 41), S(4, 56), S(8, 59),
+    S(15, 66), S(15, 71), S(16, 77), S(19, 82), S(23, 83), S(24, 84), S(29, 86),
+];
+pub const QUEEN_MOBILITY: [S; 28] = [
+    S(-20, -18), S(-10, -8), S(2, 4), S(2, 9), S(7, 17), S(11, 27), S(14, 30), S(20, 36), S(22, 40), S(24, 46),
+    S(28, 47), S(30, 52), S(30, 56), S(33, 60), S(34, 62), S(35, 63), S(36, 66), S(37, 68), S(40, 70), S(44, 72),
+    S(44, 74), S(50, 83), S(51, 85), S(51, 88), S(53, 92), S(55, 96), S(57, 103), S(58, 106),
+];
+pub const BISHOP_PAIR: S = S(26, 52);
+pub const ROOK_OPEN: S = S(24, 10);
+pub const ROOK_SEMI_OPEN: S = S(10, 6);
+pub const KNIGHT_OUTPOST: S = S(22, 12);
+pub const BISHOP_OUTPOST: S = S(12, 6);
+pub const THREAT_BY_PAWN: S = S(44, 30);
+pub const THREAT_BY_MINOR: S = S(24, 18);
+pub const THREAT_BY_ROOK: S = S(30, 16);
+
+// king safety, attack units by attacking piece
+pub const ATTACK_UNITS: [i16; 6] = [0, 2, 2, 3, 5, 0];
+pub const KING_DANGER_MAX: i16 = 600;
+pub const PAWN_SHIELD: [S; 3] = [S(0, 0), S(14, -2), S(7, -1)];
+pub const PAWN_STORM: [S; 8] = [S(0, 0), S(0, 0), S(-24, 0), S(-14, 0), S(-6, 0), S(0, 0), S(0, 0), S(0, 0)];
+
 // move ordering
-pub const HASH_MOVE: u16 = 30000;
-pub const PROMOTION: u16 = 600;
-pub const KILLER: u16 = 500;
-pub const QUIET: u16 = 0;
+pub const HASH_MOVE: u16 = 65000;
+pub const CAPTURE: u16 = 55000;
+pub const PROMOTION: u16 = 51000;
+pub const KILLER: u16 = 50000;
+pub const COUNTER_MOVE: u16 = 49900;
+pub const QUIET: u16 = 25000;
+/// Losing captures are picked after every quiet, but their scores still overlap with those of quiets
+/// with poor history, which go as low as `QUIET - 3 * HISTORY_MAX`
+pub const BAD_CAPTURE: u16 = 10000;
+
+// pruning, late move pruning by depth
+pub const SEE_VALS: [i16; 7] = [100, 300, 300, 500, 900, 0, 0];
+pub const LMP_MOVES: [u16; 9] = [0, 5, 7, 11, 17, 25, 35, 47, 61];
+
+// late move reductions, ln(depth) * ln(move number) based
+pub const LMR_BASE: f64 = 0.75;
+pub const LMR_DIVISOR: f64 = 2.25;
+
+// singular extensions, from this depth, and double extensions when far below the singular beta
+pub const SINGULAR_DEPTH: i8 = 8;
+pub const DOUBLE_EXT_MARGIN: i16 = 20;
+pub const MAX_DOUBLE_EXTS: u8 = 6;
+
+// aspiration windows, starting from this depth
+pub const ASPIRATION_DEPTH: i8 = 4;
+pub const ASPIRATION_WINDOW: i16 = 25;
+pub const ASPIRATION_MAX: i16 = 1000;
+
+// quiet history, quiets are scored QUIET + butterfly + both continuation histories
+pub const HISTORY_MAX: i32 = 8192;
+pub const HISTORY_BONUS_MAX: i16 = 1600;
 pub const MVV_LVA: [[u16; 7]; 7] = [[1500, 1400, 1300, 1200, 1100, 1000, 0], [2500, 2400, 2300, 2200, 2100, 2000, 0], [3500, 3400, 3300, 3200, 3100, 3000, 0], [4500, 4400, 4300, 4200, 4100, 4000, 0], [5500, 5400, 5300, 5200, 5100, 5000,0], [0, 0, 0, 0, 0, 0, 0], [0, 0, 0, 0, 0, 0, 0]];
 
 // eval values
@@ -179,3 +252,21 @@ pub const FRC_POSITIONS: [(&str, u8, u64); 5] = [
     ("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", 5, 9183776),
     ("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", 5, 34030312)
 ];
+
+// fixed depth search benchmark
+pub const BENCH_DEPTH: i8 = 9;
+pub const FUZZ_PLIES: usize = 60;
+pub const BENCH_POSITIONS: [&str; 12] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bq1rk1/pp2bppp/2n1pn2/2pp4/3P4/2PBPN2/PP1N1PPP/R2QK2R w KQ - 0 8",
+    "2r3k1/pp3ppp/4p3/3pP3/3P1P2/1P2K3/P5PP/2R5 b - - 0 25",
+    "r2q1rk1/1b2bppp/p2ppn2/1p6/3NP3/1BN1B3/PPP2PPP/R2Q1RK1 w - - 0 12",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+    "r1b2rk1/ppq1bppp/2n1pn2/3p4/2PP4/2N1PN2/PPQ2PPP/R1B1KB1R w KQ - 0 9",
+    "3r2k1/p4ppp/1p6/2p5/2P1n3/1P2P3/P3BPPP/3R2K1 b - - 0 24",
+];
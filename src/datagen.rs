@@ -0,0 +1,211 @@
+use std::{fs::File, io::{BufWriter, Write}, sync::mpsc, thread, time::{Instant, SystemTime, UNIX_EPOCH}};
+use super::{consts::*, position::Position, movegen::MoveList, search::{go, SearchContext}, tables::{HashTable, KillerTable}, parse_fen, to_fen};
+
+const RANDOM_PLIES: u64 = 8;
+const MAX_OPENING_SCORE: i16 = 1000;
+const WIN_SCORE: i16 = 1000;
+const WIN_PLIES: usize = 4;
+const DRAW_SCORE: i16 = 10;
+const DRAW_PLIES: usize = 10;
+const DRAW_MIN_PLY: usize = 80;
+const MAX_GAME_PLY: usize = 400;
+
+/// Simple xorshift generator, seeded per thread.
+pub struct Rng(pub u64);
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// A recorded position, with the search score (white relative) and the eventual game result.
+struct Sample {
+    fen: String,
+    occ: u64,
+    pieces: [u8; 16],
+    stm: u8,
+    score: i16,
+}
+
+impl Sample {
+    fn new(pos: &Position, score: i16) -> Self {
+        let occ: u64 = pos.sides[0] | pos.sides[1];
+        let mut pieces: [u8; 16] = [0; 16];
+        let mut bb: u64 = occ;
+        let mut i: usize = 0;
+        while bb > 0 {
+            let sq: usize = bb.trailing_zeros() as usize;
+            let side: u8 = u8::from(pos.sides[BLACK] & (1 << sq) > 0);
+            pieces[i / 2] |= ((side << 3) | pos.squares[sq]) << (4 * (i & 1));
+            bb &= bb - 1;
+            i += 1;
+        }
+        Self { fen: to_fen(pos), occ, pieces, stm: u8::from(pos.c), score }
+    }
+
+    /// 32 byte little endian record:
+    /// - occupancy (8 bytes)
+    /// - a nibble per occupied square, in square order, of `colour << 3 | piece` (16 bytes)
+    /// - white relative score (2 bytes)
+    /// - result for white, 0 = loss, 1 = draw, 2 = win (1 byte)
+    /// - side to move (1 byte)
+    /// - padding (4 bytes)
+    fn to_bytes(&self, result: u8) -> [u8; 32] {
+        let mut bytes: [u8; 32] = [0; 32];
+        bytes[..8].copy_from_slice(&self.occ.to_le_bytes());
+        bytes[8..24].copy_from_slice(&self.pieces);
+        bytes[24..26].copy_from_slice(&self.score.to_le_bytes());
+        bytes[26] = result;
+        bytes[27] = self.stm;
+        bytes
+    }
+}
+
+/// Chess960 starting position from its standard index.
+fn frc_fen(mut idx: usize) -> String {
+    let mut rank: [char; 8] = ['.'; 8];
+    rank[2 * (idx % 4) + 1] = 'B';
+    idx /= 4;
+    rank[2 * (idx % 4)] = 'B';
+    idx /= 4;
+    let place = |rank: &mut [char; 8], mut n: usize, piece: char| {
+        for sq in rank.iter_mut().filter(|sq| **sq == '.') {
+            if n == 0 {
+                *sq = piece;
+                return
+            }
+            n -= 1;
+        }
+    };
+    place(&mut rank, idx % 6, 'Q');
+    idx /= 6;
+    let (n1, n2): (usize, usize) = [(0, 0), (0, 1), (0, 2), (0, 3), (1, 1), (1, 2), (1, 3), (2, 2), (2, 3), (3, 3)][idx];
+    place(&mut rank, n1, 'N');
+    place(&mut rank, n2, 'N');
+    for piece in ['R', 'K', 'R'] { place(&mut rank, 0, piece) }
+    let white: String = rank.iter().collect();
+    let rooks: Vec<char> = rank.iter().enumerate().filter(|(_, &pc)| pc == 'R').map(|(file, _)| (b'A' + file as u8) as char).collect();
+    let castle: String = format!("{}{}{}{}", rooks[1], rooks[0], rooks[1].to_ascii_lowercase(), rooks[0].to_ascii_lowercase());
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{white} w {castle} - 0 1", white.to_lowercase())
+}
+
+pub fn legal_moves(pos: &mut Position) -> Vec<u16> {
+    let mut moves: MoveList = MoveList::default();
+    pos.gen_moves::<ALL>(&mut moves);
+    (0..moves.len).map(|i| moves.list[i]).filter(|&m| {
+        let illegal: bool = pos.do_move(m);
+        if !illegal { pos.undo_move() }
+        !illegal
+    }).collect()
+}
+
+/// Plays random plies from the start, retrying until the resulting position is playable.
+fn random_opening(rng: &mut Rng, frc: bool) -> Position {
+    loop {
+        let mut pos: Position = parse_fen(&if frc {frc_fen(rng.next() as usize % 960)} else {STARTPOS.to_string()});
+        let plies: u64 = RANDOM_PLIES + rng.next() % 2;
+        let mut ok: bool = true;
+        for _ in 0..plies {
+            let moves: Vec<u16> = legal_moves(&mut pos);
+            if moves.is_empty() {
+                ok = false;
+                break
+            }
+            pos.do_move(moves[rng.next() as usize % moves.len()]);
+        }
+        if ok && !legal_moves(&mut pos).is_empty() { return pos }
+    }
+}
+
+/// Plays a single game, returning the quiet positions and the result for white,
+/// or nothing if the opening was too unbalanced.
+fn play_game(rng: &mut Rng, ctx: &mut SearchContext, nodes: u64, frc: bool) -> Option<(Vec<Sample>, u8)> {
+    let mut pos: Position = random_opening(rng, frc);
+    ctx.hash_table.clear();
+    ctx.max_nodes = nodes;
+    let (_, opening_score): (u16, i16) = go(&mut pos, MAX_PLY - 1, ctx);
+    if opening_score.abs() > MAX_OPENING_SCORE { return None }
+
+    let mut samples: Vec<Sample> = Vec::new();
+    let (mut win_plies, mut loss_plies, mut draw_plies): (usize, usize, usize) = (0, 0, 0);
+    for ply in 0..MAX_GAME_PLY {
+        if legal_moves(&mut pos).is_empty() {
+            let stm_lost: bool = pos.is_in_check();
+            return Some((samples, if !stm_lost {1} else if pos.c {2} else {0}))
+        }
+        if pos.fifty_draw() || pos.repetition_draw(3) || pos.material_draw() { return Some((samples, 1)) }
+
+        let (m, score): (u16, i16) = go(&mut pos, MAX_PLY - 1, ctx);
+        let white_score: i16 = SIDE_FACTOR[usize::from(pos.c)] * score;
+
+        // adjudication
+        win_plies = if white_score >= WIN_SCORE {win_plies + 1} else {0};
+        loss_plies = if white_score <= -WIN_SCORE {loss_plies + 1} else {0};
+        draw_plies = if white_score.abs() <= DRAW_SCORE {draw_plies + 1} else {0};
+        if win_plies >= WIN_PLIES { return Some((samples, 2)) }
+        if loss_plies >= WIN_PLIES { return Some((samples, 0)) }
+        if ply >= DRAW_MIN_PLY && draw_plies >= DRAW_PLIES { return Some((samples, 1)) }
+
+        // only keep quiet positions
+        if !pos.is_in_check() && m & 0b1100_0000_0000_0000 == 0 && score.abs() < MATE_THRESHOLD {
+            samples.push(Sample::new(&pos, white_score));
+        }
+        pos.do_move(m);
+    }
+    Some((samples, 1))
+}
+
+/// Plays self-play games across threads, writing `<out>.txt` (`fen | score | result`)
+/// and `<out>.bin` (32 byte records).
+pub fn run(games: usize, threads: usize, nodes: u64, out: &str, frc: bool) -> std::io::Result<()> {
+    let mut text: BufWriter<File> = BufWriter::new(File::create(format!("{out}.txt"))?);
+    let mut binary: BufWriter<File> = BufWriter::new(File::create(format!("{out}.bin"))?);
+    let seed: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64);
+    let (sender, receiver) = mpsc::channel::<(Vec<Sample>, u8)>();
+    let timer: Instant = Instant::now();
+    thread::scope(|s| -> std::io::Result<()> {
+        for id in 0..threads {
+            let sender: mpsc::Sender<(Vec<Sample>, u8)> = sender.clone();
+            let games: usize = games / threads + usize::from(id < games % threads);
+            s.spawn(move || {
+                let mut rng: Rng = Rng(seed ^ (0x9E37_79B9_7F4A_7C15u64.wrapping_mul(id as u64 + 1)) | 1);
+                let mut ctx: SearchContext = SearchContext::new(HashTable::new(), KillerTable([[0; KILLERS_PER_PLY]; MAX_PLY as usize]));
+                if let Err(err) = ctx.hash_table.resize(16) {
+                    println!("info string failed to allocate hash: {err}");
+                    return
+                }
+                ctx.alloc_time = u128::MAX;
+                ctx.silent = true;
+                let mut played: usize = 0;
+                while played < games {
+                    let Some(game) = play_game(&mut rng, &mut ctx, nodes, frc) else { continue };
+                    if sender.send(game).is_err() { return }
+                    played += 1;
+                }
+            });
+        }
+        drop(sender);
+
+        let (mut played, mut positions): (usize, usize) = (0, 0);
+        let mut results: [usize; 3] = [0; 3];
+        for (samples, result) in receiver {
+            played += 1;
+            results[result as usize] += 1;
+            positions += samples.len();
+            for sample in samples {
+                writeln!(text, "{} | {} | {}", sample.fen, sample.score, ["0.0", "0.5", "1.0"][result as usize])?;
+                binary.write_all(&sample.to_bytes(result))?;
+            }
+            if played % 100 == 0 || played == games {
+                let secs: f64 = timer.elapsed().as_secs_f64();
+                println!("info string games {played} positions {positions} (+{} ={} -{}) pos/s {:.0}", results[2], results[1], results[0], positions as f64 / secs);
+            }
+        }
+        Ok(())
+    })?;
+    text.flush()?;
+    binary.flush()
+}
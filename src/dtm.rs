@@ -0,0 +1,358 @@
+use std::{collections::HashMap, fs, path::Path, time::Instant};
+use super::{lsb, consts::*, position::Position, movegen::MoveList, syzygy::material, parse_fen};
+
+const MAGIC: &[u8; 4] = b"AKTB";
+const VERSION: u8 = 1;
+const MAX_PIECES: usize = 4;
+const PIECE_ORDER: &str = "KQRBNP";
+const EMPTY_BOARD: &str = "8/8/8/8/8/8/8/8 w - - 0 1";
+
+/// Squares the white king is mapped to by symmetry: a1-d1-d4 triangle without pawns, files a-d with them.
+const TRIANGLE: [usize; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+/// Sorts a side's pieces into naming order, e.g. "KPR" -> "KRP".
+fn sort_side(side: &str) -> String {
+    let mut chars: Vec<char> = side.chars().collect();
+    chars.sort_by_key(|&c| PIECE_ORDER.find(c).unwrap_or(6));
+    chars.into_iter().collect()
+}
+
+/// Table name with the stronger side first, and whether colours had to be swapped to get there.
+fn canonical(white: &str, black: &str) -> (String, bool) {
+    let strength = |s: &str| s.chars().map(|c| 6 - PIECE_ORDER.find(c).unwrap_or(6)).collect::<Vec<usize>>();
+    if strength(white) >= strength(black) {
+        (format!("{white}v{black}"), false)
+    } else {
+        (format!("{black}v{white}"), true)
+    }
+}
+
+/// Depth-to-mate table for one material signature.
+/// Each entry is `plies to mate + 1` for the side to move, so odd values are losses,
+/// even values are wins, and 0 is a draw (or an illegal position).
+pub struct DtmTable {
+    name: String,
+    pieces: Vec<(usize, usize)>,
+    pawns: bool,
+    values: Vec<u8>,
+}
+
+impl DtmTable {
+    /// Creates an empty table from a name like "KBNvK", with white's king as the first piece.
+    fn new(name: &str) -> Option<Self> {
+        let (white, black): (&str, &str) = name.split_once('v')?;
+        let mut pieces: Vec<(usize, usize)> = Vec::new();
+        for (side, s) in [(WHITE, white), (BLACK, black)] {
+            if !s.starts_with('K') || s.matches('K').count() != 1 { return None }
+            for ch in s.chars() {
+                pieces.push((side, KING - PIECE_ORDER.find(ch)?));
+            }
+        }
+        if pieces.len() > MAX_PIECES { return None }
+        let pawns: bool = name.contains('P');
+        let size: usize = 2 * if pawns {32} else {10} * 64usize.pow(pieces.len() as u32 - 1);
+        Some(Self { name: name.to_string(), pieces, pawns, values: vec![0; size] })
+    }
+
+    /// Maps the squares (white king first) to an index, applying board symmetries in place.
+    fn index(&self, squares: &mut [usize], stm: usize) -> usize {
+        if squares[0] & 7 > 3 { squares.iter_mut().for_each(|sq| *sq ^= 7) }
+        if !self.pawns {
+            if squares[0] >> 3 > 3 { squares.iter_mut().for_each(|sq| *sq ^= 56) }
+            if squares[0] >> 3 > squares[0] & 7 { squares.iter_mut().for_each(|sq| *sq = ((*sq & 7) << 3) | (*sq >> 3)) }
+        }
+        // identical pieces are interchangeable, so keep them in ascending order
+        for i in 2..squares.len() {
+            let mut j: usize = i;
+            while j > 1 && self.pieces[j] == self.pieces[j - 1] && squares[j] < squares[j - 1] {
+                squares.swap(j, j - 1);
+                j -= 1;
+            }
+        }
+        let lead: usize = if self.pawns {
+            4 * (squares[0] >> 3) + (squares[0] & 7)
+        } else {
+            TRIANGLE.iter().position(|&sq| sq == squares[0]).unwrap_or(0)
+        };
+        let mut idx: usize = stm * if self.pawns {32} else {10} + lead;
+        for &sq in &squares[1..] { idx = 64 * idx + sq }
+        idx
+    }
+
+    /// Index of a position with this table's material, optionally with colours swapped.
+    fn index_of(&self, pos: &Position, flip: bool) -> usize {
+        let mut squares: [usize; MAX_PIECES] = [0; MAX_PIECES];
+        let mut used: u64 = 0;
+        let flip_side: usize = usize::from(flip);
+        for (i, &(side, pc)) in self.pieces.iter().enumerate() {
+            let sq: usize = lsb!(pos.pieces[pc] & pos.sides[side ^ flip_side] & !used) as usize;
+            used |= 1 << sq;
+            squares[i] = sq ^ (56 * flip_side);
+        }
+        self.index(&mut squares[..self.pieces.len()], usize::from(pos.c) ^ flip_side)
+    }
+
+    /// Sets up the position with the given index, returning false if it is illegal.
+    fn decode(&self, mut idx: usize, pos: &mut Position) -> bool {
+        let n: usize = self.pieces.len();
+        let mut squares: [usize; MAX_PIECES] = [0; MAX_PIECES];
+        for i in (1..n).rev() {
+            squares[i] = idx & 63;
+            idx >>= 6;
+        }
+        let leads: usize = if self.pawns {32} else {10};
+        squares[0] = if self.pawns {8 * ((idx % leads) / 4) + idx % 4} else {TRIANGLE[idx % leads]};
+        pos.c = idx / leads == 1;
+        pos.pieces = [0; 6];
+        pos.sides = [0; 2];
+        pos.squares = [EMPTY as u8; 64];
+        for (&(side, pc), &sq) in self.pieces.iter().zip(squares.iter()) {
+            if pos.squares[sq] != EMPTY as u8 || (pc == PAWN && !(8..56).contains(&sq)) { return false }
+            pos.pieces[pc] |= 1 << sq;
+            pos.sides[side] |= 1 << sq;
+            pos.squares[sq] = pc as u8;
+        }
+        // side that just moved can't be in check
+        let king: usize = lsb!(pos.pieces[KING] & pos.sides[usize::from(!pos.c)]) as usize;
+        !pos.is_square_attacked(king, usize::from(!pos.c), pos.sides[0] | pos.sides[1])
+    }
+
+    /// Value of the position reached after `m`, from the new side to move's perspective.
+    fn child_value(&self, pos: &Position, m: u16, tables: &HashMap<String, DtmTable>) -> u8 {
+        if m & 0b1100_0000_0000_0000 == 0 { return self.values[self.index_of(pos, false)] }
+        let (name, flip): (String, bool) = canonical(&material(pos, WHITE), &material(pos, BLACK));
+        tables.get(&name).map_or(0, |table| table.values[table.index_of(pos, flip)])
+    }
+
+    /// Forward retrograde analysis:
+    /// - Checkmates are losses in 0 plies
+    /// - On odd plies, a position is won if any move reaches a loss one ply shorter
+    /// - On even plies, a position is lost if every move reaches an already known win
+    /// - Whatever is unresolved once nothing changes (and no sub-table can still feed in) is drawn
+    fn solve(&mut self, tables: &HashMap<String, DtmTable>) {
+        let mut pos: Position = parse_fen(EMPTY_BOARD);
+        let mut done: Vec<bool> = vec![false; self.values.len()];
+        for (idx, finished) in done.iter_mut().enumerate() {
+            if !self.decode(idx, &mut pos) {
+                *finished = true;
+            } else if !pos.has_legal_moves() {
+                *finished = true;
+                if pos.is_in_check() { self.values[idx] = 1 }
+            }
+        }
+        let max_sub: usize = tables.values().flat_map(|t| t.values.iter()).copied().max().unwrap_or(0) as usize;
+        let mut last_change: usize = 0;
+        let mut p: usize = 1;
+        while (p <= last_change + 1 || p <= max_sub + 1) && p < u8::MAX as usize {
+            let losing_pass: bool = p & 1 == 0;
+            for (idx, finished) in done.iter_mut().enumerate() {
+                if *finished { continue }
+                self.decode(idx, &mut pos);
+                let mut moves: MoveList = MoveList::default();
+                pos.gen_moves::<ALL>(&mut moves);
+                let mut resolved: bool = losing_pass;
+                for i in 0..moves.len {
+                    let m: u16 = moves.list[i];
+                    if pos.do_move(m) { continue }
+                    let v: usize = self.child_value(&pos, m, tables) as usize;
+                    pos.undo_move();
+                    if !losing_pass && v == p {
+                        resolved = true;
+                        break
+                    }
+                    if losing_pass && (v == 0 || v & 1 == 1 || v > p) {
+                        resolved = false;
+                        break
+                    }
+                }
+                if resolved {
+                    self.values[idx] = p as u8 + 1;
+                    *finished = true;
+                    last_change = p;
+                }
+            }
+            p += 1;
+        }
+    }
+
+    /// Run-length encoded (value, varint run) pairs behind a small header.
+    fn save(&self, dir: &Path) -> std::io::Result<()> {
+        let mut out: Vec<u8> = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&(self.values.len() as u32).to_le_bytes());
+        let mut i: usize = 0;
+        while i < self.values.len() {
+            let value: u8 = self.values[i];
+            let mut run: usize = self.values[i..].iter().take_while(|&&v| v == value).count();
+            i += run;
+            out.push(value);
+            while run >= 0x80 {
+                out.push(run as u8 | 0x80);
+                run >>= 7;
+            }
+            out.push(run as u8);
+        }
+        fs::write(dir.join(format!("{}.dtm", self.name)), out)
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let mut table: Self = Self::new(path.file_stem()?.to_str()?)?;
+        let data: Vec<u8> = fs::read(path).ok()?;
+        if data.len() < 9 || &data[..4] != MAGIC || data[4] != VERSION { return None }
+        if u32::from_le_bytes(data[5..9].try_into().ok()?) as usize != table.values.len() { return None }
+        let mut values: Vec<u8> = Vec::with_capacity(table.values.len());
+        let mut i: usize = 9;
+        while i < data.len() {
+            let value: u8 = data[i];
+            let (mut run, mut shift): (usize, u32) = (0, 0);
+            loop {
+                i += 1;
+                let byte: u8 = *data.get(i)?;
+                run |= ((byte & 0x7F) as usize) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 { break }
+            }
+            i += 1;
+            values.extend(std::iter::repeat_n(value, run));
+        }
+        if values.len() != table.values.len() { return None }
+        table.values = values;
+        Some(table)
+    }
+}
+
+/// Every table reachable from this one by a capture or promotion, excluding bare kings.
+fn sub_tables(name: &str) -> Vec<String> {
+    let Some((white, black)) = name.split_once('v') else { return Vec::new() };
+    let mut subs: Vec<String> = Vec::new();
+    for (side, other, white_first) in [(white, black, true), (black, white, false)] {
+        for (i, ch) in side.char_indices().filter(|&(_, ch)| ch != 'K') {
+            let removed: String = format!("{}{}", &side[..i], &side[i + 1..]);
+            let mut options: Vec<String> = vec![removed.clone()];
+            if ch == 'P' { options.extend("QRBN".chars().map(|promo| sort_side(&format!("{removed}{promo}")))) }
+            for option in options {
+                let (w, b): (&str, &str) = if white_first {(&option, other)} else {(other, &option)};
+                if w.len() + b.len() > 2 { subs.push(canonical(w, b).0) }
+            }
+        }
+    }
+    subs
+}
+
+fn generate_recursive(name: &str, dir: &Path, tables: &mut HashMap<String, DtmTable>) -> std::io::Result<()> {
+    if tables.contains_key(name) { return Ok(()) }
+    for sub in sub_tables(name) { generate_recursive(&sub, dir, tables)? }
+    let Some(mut table) = DtmTable::new(name) else { return Ok(()) };
+    let now: Instant = Instant::now();
+    table.solve(tables);
+    let wins: usize = table.values.iter().filter(|&&v| v > 0 && v % 2 == 0).count();
+    let losses: usize = table.values.iter().filter(|&&v| v % 2 == 1).count();
+    let longest: u8 = table.values.iter().copied().max().unwrap_or(1).saturating_sub(1);
+    println!("info string {name}: {wins} wins {losses} losses longest mate {longest} plies time {}", now.elapsed().as_millis());
+    table.save(dir)?;
+    tables.insert(name.to_string(), table);
+    Ok(())
+}
+
+/// Table name for a material signature, with or without the 'v' (e.g. "KBNvK" or "KBNK").
+fn signature_name(signature: &str) -> Option<String> {
+    let (white, black): (&str, &str) = signature.split_once('v')
+        .or_else(|| signature.get(1..)?.find('K').map(|i| signature.split_at(i + 1)))?;
+    let (name, _): (String, bool) = canonical(&sort_side(white), &sort_side(black));
+    DtmTable::new(&name).map(|_| name)
+}
+
+/// Generates the table for a material signature (e.g. "KRvK") and all of its sub-tables into `dir`.
+pub fn generate(signature: &str, dir: &str) -> std::io::Result<()> {
+    let Some(name) = signature_name(signature) else {
+        println!("info string invalid signature {signature}, expected e.g. KBNvK with both kings and at most {MAX_PIECES} pieces");
+        return Ok(())
+    };
+    fs::create_dir_all(dir)?;
+    generate_recursive(&name, Path::new(dir), &mut HashMap::new())
+}
+
+/// Generated depth-to-mate tables, probed by the search for exact mate scores.
+#[derive(Default)]
+pub struct DtmTables {
+    tables: HashMap<String, DtmTable>,
+    max_pieces: u32,
+}
+
+impl DtmTables {
+    /// Loads every table found in the given directories (separated as in `PATH`).
+    pub fn new(paths: &str) -> Self {
+        let mut dtm: Self = Self::default();
+        if paths.is_empty() || paths == "<empty>" { return dtm }
+        for dir in std::env::split_paths(paths) {
+            let Ok(entries) = fs::read_dir(&dir) else { continue };
+            for path in entries.flatten().map(|entry| entry.path()).filter(|path| path.extension().is_some_and(|ext| ext == "dtm")) {
+                if let Some(table) = DtmTable::load(&path) {
+                    dtm.max_pieces = std::cmp::max(dtm.max_pieces, table.pieces.len() as u32);
+                    dtm.tables.insert(table.name.clone(), table);
+                }
+            }
+        }
+        println!("info string loaded {} dtm tables", dtm.tables.len());
+        dtm
+    }
+
+    /// Exact mate score (or draw) for the side to move, if the position is covered
+    /// and the mate can't be cut short by the fifty-move rule.
+    pub fn probe(&self, pos: &Position, ply: i16) -> Option<i16> {
+        if (pos.sides[0] | pos.sides[1]).count_ones() > self.max_pieces || pos.state.castle_rights > 0 || pos.state.en_passant_sq > 0 {
+            return None
+        }
+        let (name, flip): (String, bool) = canonical(&material(pos, WHITE), &material(pos, BLACK));
+        let table: &DtmTable = self.tables.get(&name)?;
+        let value: i16 = table.values[table.index_of(pos, flip)] as i16;
+        if value > 0 && i16::from(pos.state.halfmove_clock) + value > 100 { return None }
+        Some(match value {
+            0 => 0,
+            v if v % 2 == 0 => MAX - ply - (v - 1),
+            v => -MAX + ply + (v - 1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signatures() {
+        assert_eq!(signature_name("KBNvK").as_deref(), Some("KBNvK"));
+        assert_eq!(signature_name("KBNK").as_deref(), Some("KBNvK"));
+        assert_eq!(signature_name("KKNB").as_deref(), Some("KBNvK"));
+        assert_eq!(signature_name("KPvKR").as_deref(), Some("KRvKP"));
+        assert_eq!(signature_name("KRPK").as_deref(), Some("KRPvK"));
+        for invalid in ["KBN", "BNvK", "KQvKvK", "KQRBvK", "KXvK", ""] { assert_eq!(signature_name(invalid), None) }
+    }
+
+    #[test]
+    fn generates_kqk() {
+        let dir: std::path::PathBuf = std::env::temp_dir().join("akimbo_dtm_kqk");
+        fs::create_dir_all(&dir).unwrap();
+        let mut tables: HashMap<String, DtmTable> = HashMap::new();
+        generate_recursive("KQvK", &dir, &mut tables).unwrap();
+        let dtm: DtmTables = DtmTables::new(&dir.to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the saved table loads back unchanged, and the longest mate is the well known 10 moves
+        let values: &[u8] = &dtm.tables["KQvK"].values;
+        assert_eq!(values, tables["KQvK"].values);
+        assert_eq!(values.iter().copied().filter(|v| v % 2 == 0).max(), Some(20));
+
+        let probe = |fen: &str| dtm.probe(&parse_fen(fen), 0);
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), Some(MAX - 1));
+        assert_eq!(probe("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1"), Some(MAX - 1));
+        assert_eq!(probe("6q1/8/8/8/8/1k6/8/K7 b - - 0 1"), Some(MAX - 1));
+        assert_eq!(probe("k5Q1/8/1K6/8/8/8/8/8 b - - 0 1"), Some(-MAX));
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Some(0));
+        assert_eq!(probe("k7/1Q6/8/8/8/8/8/7K b - - 0 1"), Some(0));
+
+        // a mate that the fifty-move rule would cut short isn't reported
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/6Q1 w - - 98 1"), Some(MAX - 1));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/6Q1 w - - 99 1"), None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::OnceLock;
+use super::{lsb, consts::*, position::Position};
+
+// material signatures, one nibble per piece type with pawns lowest
+const SIG_PAWN: u32 = 1;
+const SIG_KNIGHT: u32 = 1 << 4;
+const SIG_BISHOP: u32 = 1 << 8;
+const SIG_ROOK: u32 = 1 << 12;
+const SIG_PIECES: u32 = !0xF;
+
+// kpk bitbase
+const KPK_SIZE: usize = 2 * 64 * 64 * 24;
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+static KPK: OnceLock<Vec<u64>> = OnceLock::new();
+
+// scale factors, out of 64
+const SCALE_NORMAL: i32 = 64;
+const SCALE_OPPOSITE_BISHOPS: i32 = 32;
+const SCALE_KRKB: i32 = 8;
+
+const DARK_SQUARES: u64 = 0xAA55_AA55_AA55_AA55;
+
+#[inline]
+fn distance(a: usize, b: usize) -> usize {
+    std::cmp::max((a & 7).abs_diff(b & 7), (a >> 3).abs_diff(b >> 3))
+}
+
+/// Distance from the centre, 0 in the middle four squares and 6 in the corners.
+#[inline]
+fn edge_distance(sq: usize) -> usize {
+    let (file, rank): (usize, usize) = (sq & 7, sq >> 3);
+    std::cmp::max(3usize.saturating_sub(file), file.saturating_sub(4)) + std::cmp::max(3usize.saturating_sub(rank), rank.saturating_sub(4))
+}
+
+/// Index into the bitbase, with white as the side with the pawn and the pawn on files a-d.
+#[inline]
+fn kpk_index(stm: usize, wk: usize, bk: usize, psq: usize) -> usize {
+    stm | (bk << 1) | (wk << 7) | ((4 * ((psq >> 3) - 1) + (psq & 7)) << 13)
+}
+
+fn kpk_decode(idx: usize) -> (usize, usize, usize, usize) {
+    let p: usize = idx >> 13;
+    (idx & 1, (idx >> 7) & 63, (idx >> 1) & 63, 8 * (p / 4 + 1) + p % 4)
+}
+
+/// Classifies positions that can be decided without looking at any moves.
+fn kpk_init(idx: usize) -> u8 {
+    let (stm, wk, bk, psq): (usize, usize, usize, usize) = kpk_decode(idx);
+    if distance(wk, bk) <= 1 || wk == psq || bk == psq || (stm == WHITE && PAWN_ATTACKS[WHITE][psq] & (1 << bk) > 0) {
+        INVALID
+    } else if stm == WHITE && psq >> 3 == 6 && wk != psq + 8 && (distance(bk, psq + 8) > 1 || distance(wk, psq + 8) == 1) {
+        WIN
+    } else if stm == BLACK && (KING_ATTACKS[bk] & !(KING_ATTACKS[wk] | PAWN_ATTACKS[WHITE][psq]) == 0 || KING_ATTACKS[bk] & (1 << psq) & !KING_ATTACKS[wk] > 0) {
+        DRAW
+    } else {
+        UNKNOWN
+    }
+}
+
+/// White wins if any move wins, black draws if any move draws.
+fn kpk_classify(db: &[u8], idx: usize) -> u8 {
+    let (stm, wk, bk, psq): (usize, usize, usize, usize) = kpk_decode(idx);
+    let (good, bad): (u8, u8) = if stm == WHITE {(WIN, DRAW)} else {(DRAW, WIN)};
+    let mut r: u8 = INVALID;
+    let mut moves: u64 = KING_ATTACKS[if stm == WHITE {wk} else {bk}];
+    while moves > 0 {
+        let sq: usize = lsb!(moves) as usize;
+        r |= if stm == WHITE {db[kpk_index(BLACK, sq, bk, psq)]} else {db[kpk_index(WHITE, wk, sq, psq)]};
+        moves &= moves - 1;
+    }
+    if stm == WHITE && psq < 48 {
+        r |= db[kpk_index(BLACK, wk, bk, psq + 8)];
+        if psq < 16 && psq + 8 != wk && psq + 8 != bk { r |= db[kpk_index(BLACK, wk, bk, psq + 16)] }
+    }
+    if r & good > 0 {good} else if r & UNKNOWN > 0 {UNKNOWN} else {bad}
+}
+
+fn generate_kpk() -> Vec<u64> {
+    let mut db: Vec<u8> = (0..KPK_SIZE).map(kpk_init).collect();
+    let mut changed: bool = true;
+    while changed {
+        changed = false;
+        for idx in 0..KPK_SIZE {
+            if db[idx] == UNKNOWN {
+                db[idx] = kpk_classify(&db, idx);
+                changed |= db[idx] != UNKNOWN;
+            }
+        }
+    }
+    let mut bits: Vec<u64> = vec![0; KPK_SIZE / 64];
+    for (idx, &res) in db.iter().enumerate() {
+        if res == WIN { bits[idx / 64] |= 1 << (idx % 64) }
+    }
+    bits
+}
+
+/// Whether the side with the pawn wins, given squares with that side as white.
+fn kpk_probe(stm: usize, mut wk: usize, mut bk: usize, mut psq: usize) -> bool {
+    if psq & 7 > 3 {
+        wk ^= 7;
+        bk ^= 7;
+        psq ^= 7;
+    }
+    let idx: usize = kpk_index(stm, wk, bk, psq);
+    KPK.get_or_init(generate_kpk)[idx / 64] & (1 << (idx % 64)) > 0
+}
+
+impl Position {
+    /// Piece counts for one side, keying the specialised endgames.
+    fn material_signature(&self, side: usize) -> u32 {
+        (PAWN..KING).map(|pc| (self.pieces[pc] & self.sides[side]).count_ones() << (4 * pc)).sum()
+    }
+
+    /// Applies endgame knowledge to a white-relative evaluation:
+    /// - KPK is looked up in a bitbase
+    /// - Lone kings are driven to the edge (or the right corner in KBNK)
+    /// - Drawish material is scaled towards zero
+    pub fn endgame_eval(&self, eval: i16) -> i16 {
+        if self.phase > 4 && self.sides[0].count_ones() > 1 && self.sides[1].count_ones() > 1 { return eval }
+        let sigs: [u32; 2] = [self.material_signature(WHITE), self.material_signature(BLACK)];
+        for strong in [WHITE, BLACK] {
+            let weak: usize = strong ^ 1;
+            if sigs[weak] != 0 { continue }
+            let sig: u32 = sigs[strong];
+            if sig == SIG_PAWN {
+                let sq = |pc: usize, side: usize| lsb!(self.pieces[pc] & self.sides[side]) as usize ^ (56 * strong);
+                let win: bool = kpk_probe(usize::from(self.c) ^ strong, sq(KING, strong), sq(KING, weak), sq(PAWN, strong));
+                return if win {eval + SIDE_FACTOR[strong] * KNOWN_WIN} else {0}
+            }
+            if sig & SIG_PIECES == SIG_BISHOP && self.wrong_rook_pawns(strong) { return 0 }
+            if sig & 0xF == 0 { return self.mop_up(eval, strong, sig) }
+        }
+        (eval as i32 * self.scale_factor(sigs) / SCALE_NORMAL) as i16
+    }
+
+    /// Bishop and rook pawns where the bishop can't control the promotion square and the defending king is there first.
+    fn wrong_rook_pawns(&self, strong: usize) -> bool {
+        let pawns: u64 = self.pieces[PAWN] & self.sides[strong];
+        let file: u64 = if pawns & FILE == pawns {FILE} else if pawns & (FILE << 7) == pawns {FILE << 7} else { return false };
+        let queening: usize = lsb!(file) as usize + if strong == WHITE {56} else {0};
+        let bishop_dark: bool = self.pieces[BISHOP] & self.sides[strong] & DARK_SQUARES > 0;
+        let queening_dark: bool = (1 << queening) & DARK_SQUARES > 0;
+        let weak_king: usize = lsb!(self.pieces[KING] & self.sides[strong ^ 1]) as usize;
+        bishop_dark != queening_dark && distance(weak_king, queening) <= 1
+    }
+
+    /// Pawnless material against a lone king.
+    fn mop_up(&self, eval: i16, strong: usize, sig: u32) -> i16 {
+        let (knights, bishops): (u32, u32) = ((sig >> 4) & 0xF, (sig >> 8) & 0xF);
+        if sig < SIG_ROOK && bishops < 2 && (knights == 0 || bishops == 0) { return 0 }
+        let strong_king: usize = lsb!(self.pieces[KING] & self.sides[strong]) as usize;
+        let weak_king: usize = lsb!(self.pieces[KING] & self.sides[strong ^ 1]) as usize;
+        let mut bonus: usize = 20 * edge_distance(weak_king) + 10 * (7 - distance(strong_king, weak_king));
+        if sig == SIG_KNIGHT | SIG_BISHOP {
+            let (a, b): (usize, usize) = if self.pieces[BISHOP] & DARK_SQUARES > 0 {(0, 63)} else {(7, 56)};
+            bonus += 40 * (7 - std::cmp::min(distance(weak_king, a), distance(weak_king, b)));
+        }
+        eval + SIDE_FACTOR[strong] * (KNOWN_WIN + bonus as i16)
+    }
+
+    fn scale_factor(&self, sigs: [u32; 2]) -> i32 {
+        if sigs[0] & SIG_PIECES == SIG_BISHOP && sigs[1] & SIG_PIECES == SIG_BISHOP {
+            let bishops: u64 = self.pieces[BISHOP];
+            if bishops & DARK_SQUARES != bishops && bishops & !DARK_SQUARES != bishops { return SCALE_OPPOSITE_BISHOPS }
+        }
+        if (sigs[0] == SIG_ROOK && sigs[1] == SIG_BISHOP) || (sigs[0] == SIG_BISHOP && sigs[1] == SIG_ROOK) {
+            return SCALE_KRKB
+        }
+        SCALE_NORMAL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fen, syzygy::Tablebases};
+
+    fn fen(pieces: &[(usize, char)], stm: char) -> String {
+        let mut board: [char; 64] = ['1'; 64];
+        for &(sq, pc) in pieces { board[sq] = pc }
+        let ranks: Vec<String> = (0..8).rev().map(|r| board[8 * r..8 * r + 8].iter().collect()).collect();
+        format!("{} {stm} - - 0 1", ranks.join("/"))
+    }
+
+    /// Every seventh valid bitbase entry, for either colour with the pawn, against the Syzygy fixture.
+    #[test]
+    fn kpk_matches_tablebase() {
+        let mut tbs: Tablebases = Tablebases::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/syzygy"));
+        let mut wins: usize = 0;
+        for idx in (0..KPK_SIZE).step_by(7).filter(|&idx| kpk_init(idx) != INVALID) {
+            let (stm, wk, bk, psq): (usize, usize, usize, usize) = kpk_decode(idx);
+            let white: String = fen(&[(wk, 'K'), (bk, 'k'), (psq, 'P')], if stm == WHITE {'w'} else {'b'});
+            let black: String = fen(&[(wk ^ 56, 'k'), (bk ^ 56, 'K'), (psq ^ 56, 'p')], if stm == WHITE {'b'} else {'w'});
+            let wdl: i32 = tbs.probe_wdl(&mut parse_fen(&white)).unwrap();
+            let win: bool = wdl == if stm == WHITE {2} else {-2};
+            wins += usize::from(win);
+            assert_eq!(kpk_probe(stm, wk, bk, psq), win, "{white}");
+            assert_eq!(parse_fen(&white).endgame_eval(0) > 0, win, "{white}");
+            assert_eq!(parse_fen(&black).endgame_eval(0) < 0, win, "{black}");
+        }
+        assert!(wins > 0);
+    }
+}
@@ -0,0 +1,307 @@
+use super::{lsb, consts::*, position::{Position, S}, tables::{PawnHashTable, PawnEntry}, movegen::{bishop_attacks, rook_attacks}};
+
+/// Index of each tapered parameter in an evaluation trace.
+pub mod param {
+    pub const PST: usize = 0;
+    pub const DOUBLED: usize = PST + 6 * 64;
+    pub const ISOLATED: usize = DOUBLED + 1;
+    pub const BACKWARD: usize = ISOLATED + 1;
+    pub const PASSED: usize = BACKWARD + 1;
+    pub const CONNECTED: usize = PASSED + 8;
+    pub const PASSER_OWN_KING: usize = CONNECTED + 8;
+    pub const PASSER_OPP_KING: usize = PASSER_OWN_KING + 1;
+    pub const UNSTOPPABLE: usize = PASSER_OPP_KING + 1;
+    pub const KNIGHT_MOBILITY: usize = UNSTOPPABLE + 1;
+    pub const BISHOP_MOBILITY: usize = KNIGHT_MOBILITY + 9;
+    pub const ROOK_MOBILITY: usize = BISHOP_MOBILITY + 14;
+    pub const QUEEN_MOBILITY: usize = ROOK_MOBILITY + 15;
+    pub const BISHOP_PAIR: usize = QUEEN_MOBILITY + 28;
+    pub const ROOK_OPEN: usize = BISHOP_PAIR + 1;
+    pub const ROOK_SEMI_OPEN: usize = ROOK_OPEN + 1;
+    pub const KNIGHT_OUTPOST: usize = ROOK_SEMI_OPEN + 1;
+    pub const BISHOP_OUTPOST: usize = KNIGHT_OUTPOST + 1;
+    pub const THREAT_BY_PAWN: usize = BISHOP_OUTPOST + 1;
+    pub const THREAT_BY_MINOR: usize = THREAT_BY_PAWN + 1;
+    pub const THREAT_BY_ROOK: usize = THREAT_BY_MINOR + 1;
+    pub const PAWN_SHIELD: usize = THREAT_BY_ROOK + 1;
+    pub const PAWN_STORM: usize = PAWN_SHIELD + 3;
+    pub const COUNT: usize = PAWN_STORM + 8;
+}
+
+/// Records the coefficient of each parameter in an evaluation, for tuning.
+pub trait Trace {
+    fn add(&mut self, side: usize, param: usize, coeff: i16);
+    /// Contributions that aren't linear in any parameter.
+    fn fixed(&mut self, side: usize, score: S);
+}
+
+impl Trace for () {
+    #[inline(always)]
+    fn add(&mut self, _: usize, _: usize, _: i16) {}
+    #[inline(always)]
+    fn fixed(&mut self, _: usize, _: S) {}
+}
+
+#[inline]
+fn distance(a: usize, b: usize) -> i16 {
+    std::cmp::max((a & 7).abs_diff(b & 7), (a >> 3).abs_diff(b >> 3)) as i16
+}
+
+#[inline]
+fn adjacent_files(sq: usize) -> u64 {
+    let file: u64 = FILE << (sq & 7);
+    ((file << 1) & !FILE) | ((file >> 1) & NOTH)
+}
+
+/// All squares on ranks strictly in front of `sq`, from `side`'s point of view.
+#[inline]
+fn forward_ranks(side: usize, sq: usize) -> u64 {
+    let rank: usize = sq >> 3;
+    if side == WHITE {u64::MAX << (8 * (rank + 1))} else {(1 << (8 * rank)) - 1}
+}
+
+#[inline]
+fn pawn_attacks(side: usize, pawns: u64) -> u64 {
+    if side == WHITE {
+        ((pawns & !FILE) << 7) | ((pawns & NOTH) << 9)
+    } else {
+        ((pawns & !FILE) >> 9) | ((pawns & NOTH) >> 7)
+    }
+}
+
+impl Position {
+    /// Full static evaluation, relative to the side to move.
+    pub fn eval(&self, pawn_table: &mut PawnHashTable) -> i16 {
+        let entry: PawnEntry = pawn_table.probe(self.state.pawn_hash).unwrap_or_else(|| {
+            let entry: PawnEntry = self.pawn_structure(&mut ());
+            pawn_table.push(entry);
+            entry
+        });
+        let score: S = self.state.scores + entry.score + self.passers(entry.passers, &mut ()) + self.activity(&mut ());
+        SIDE_FACTOR[usize::from(self.c)] * self.endgame_eval(self.taper(score))
+    }
+
+    #[inline]
+    pub fn taper(&self, score: S) -> i16 {
+        let phase: i32 = std::cmp::min(self.phase as i32, TPHASE);
+        ((phase * score.0 as i32 + (TPHASE - phase) * score.1 as i32) / TPHASE) as i16
+    }
+
+    /// Evaluation before tapering and endgame knowledge, recording every term in the trace.
+    pub fn trace_eval<T: Trace>(&self, t: &mut T) -> S {
+        for sq in 0..64 {
+            let piece: usize = self.squares[sq] as usize;
+            if piece == EMPTY { continue }
+            let side: usize = usize::from(self.sides[BLACK] & (1 << sq) > 0);
+            t.add(side, param::PST + 64 * piece + (sq ^ (56 * usize::from(side == WHITE))), 1);
+        }
+        let entry: PawnEntry = self.pawn_structure(t);
+        self.state.scores + entry.score + self.passers(entry.passers, t) + self.activity(t)
+    }
+
+    /// King-independent pawn terms, white relative, with each side's passed pawns:
+    /// - Doubled pawns, for each pawn with a friendly pawn in front of it
+    /// - Isolated pawns, with no friendly pawns on adjacent files
+    /// - Backward pawns, behind all adjacent friendly pawns and with the stop square controlled by an enemy pawn
+    /// - Connected pawns, either defended or side by side
+    /// - Passed pawns, with no enemy pawns in front of them on the same or adjacent files
+    fn pawn_structure<T: Trace>(&self, t: &mut T) -> PawnEntry {
+        let mut entry: PawnEntry = PawnEntry { key: self.state.pawn_hash, ..Default::default() };
+        for side in [WHITE, BLACK] {
+            let own: u64 = self.pieces[PAWN] & self.sides[side];
+            let opp: u64 = self.pieces[PAWN] & self.sides[side ^ 1];
+            let mut score: S = S(0, 0);
+            let mut pawns: u64 = own;
+            while pawns > 0 {
+                let sq: usize = lsb!(pawns) as usize;
+                pawns &= pawns - 1;
+                let rank: usize = if side == WHITE {sq >> 3} else {7 - (sq >> 3)};
+                let stop: usize = if side == WHITE {sq + 8} else {sq - 8};
+                let file: u64 = FILE << (sq & 7);
+                let adjacent: u64 = adjacent_files(sq);
+                let ahead: u64 = forward_ranks(side, sq);
+                if own & file & ahead > 0 {
+                    score += DOUBLED;
+                    t.add(side, param::DOUBLED, 1);
+                }
+                if own & adjacent == 0 {
+                    score += ISOLATED;
+                    t.add(side, param::ISOLATED, 1);
+                } else if own & adjacent & !ahead == 0 && PAWN_ATTACKS[side][stop] & opp > 0 {
+                    score += BACKWARD;
+                    t.add(side, param::BACKWARD, 1);
+                }
+                let phalanx: u64 = own & adjacent & (0xFF << (sq & 56));
+                if phalanx | (PAWN_ATTACKS[side ^ 1][sq] & own) > 0 {
+                    score += CONNECTED[rank];
+                    t.add(side, param::CONNECTED + rank, 1);
+                }
+                if opp & (file | adjacent) & ahead == 0 {
+                    score += PASSED[rank];
+                    t.add(side, param::PASSED + rank, 1);
+                    entry.passers[side] |= 1 << sq;
+                }
+            }
+            entry.score += SIDE_FACTOR[side] * score;
+        }
+        entry
+    }
+
+    /// Passed pawn terms that depend on the kings or other pieces:
+    /// - Distance of both kings to the square in front of the pawn, weighted by rank
+    /// - Pawns that can't be caught by the enemy king in a pawn ending (rule of the square)
+    fn passers<T: Trace>(&self, passers: [u64; 2], t: &mut T) -> S {
+        let occ: u64 = self.sides[0] | self.sides[1];
+        let mut total: S = S(0, 0);
+        for side in [WHITE, BLACK] {
+            let own_king: usize = lsb!(self.pieces[KING] & self.sides[side]) as usize;
+            let opp_king: usize = lsb!(self.pieces[KING] & self.sides[side ^ 1]) as usize;
+            let pawn_ending: bool = self.sides[side ^ 1] & !(self.pieces[PAWN] | self.pieces[KING]) == 0;
+            let mut score: S = S(0, 0);
+            let mut pawns: u64 = passers[side];
+            while pawns > 0 {
+                let sq: usize = lsb!(pawns) as usize;
+                pawns &= pawns - 1;
+                let rank: i16 = if side == WHITE {sq >> 3} else {7 - (sq >> 3)} as i16;
+                let stop: usize = if side == WHITE {sq + 8} else {sq - 8};
+                let weight: i16 = std::cmp::max(rank - 2, 0);
+                score += (weight * distance(own_king, stop)) * PASSER_OWN_KING;
+                score += (weight * distance(opp_king, stop)) * PASSER_OPP_KING;
+                t.add(side, param::PASSER_OWN_KING, weight * distance(own_king, stop));
+                t.add(side, param::PASSER_OPP_KING, weight * distance(opp_king, stop));
+                let queening: usize = (sq & 7) + if side == WHITE {56} else {0};
+                let path: u64 = FILE << (sq & 7) & forward_ranks(side, sq);
+                let moves_to_go: i16 = std::cmp::min(7 - rank, 5);
+                let tempo: i16 = i16::from(usize::from(self.c) != side);
+                if pawn_ending && path & occ == 0 && distance(opp_king, queening) - tempo > moves_to_go {
+                    score += UNSTOPPABLE;
+                    t.add(side, param::UNSTOPPABLE, 1);
+                }
+            }
+            total += SIDE_FACTOR[side] * score;
+        }
+        total
+    }
+
+    /// Piece activity and king safety, white relative:
+    /// - Mobility over squares not occupied by own pawns or king and not attacked by enemy pawns
+    /// - Attack units on the enemy king zone, scaled quadratically once two pieces join in
+    /// - Pawn shield in front of and pawn storm towards a king on its first two ranks
+    /// - Bishop pair, rooks on open and semi-open files, and minor pieces on outposts
+    /// - Pieces attacked by lesser pieces
+    fn activity<T: Trace>(&self, t: &mut T) -> S {
+        let occ: u64 = self.sides[0] | self.sides[1];
+        let mut total: S = S(0, 0);
+        for side in [WHITE, BLACK] {
+            let opp: usize = side ^ 1;
+            let own_pawns: u64 = self.pieces[PAWN] & self.sides[side];
+            let opp_pawns: u64 = self.pieces[PAWN] & self.sides[opp];
+            let own_pawn_attacks: u64 = pawn_attacks(side, own_pawns);
+            let safe: u64 = !(own_pawns | (self.pieces[KING] & self.sides[side]) | pawn_attacks(opp, opp_pawns));
+            let opp_king: usize = lsb!(self.pieces[KING] & self.sides[opp]) as usize;
+            let zone: u64 = KING_ATTACKS[opp_king] | (1 << opp_king);
+            let (mut units, mut attackers): (i16, i16) = (0, 0);
+            let (mut minor_attacks, mut rook_attacks_bb): (u64, u64) = (0, 0);
+            let mut score: S = S(0, 0);
+            for pc in [KNIGHT, BISHOP, ROOK, QUEEN] {
+                let mut pieces: u64 = self.pieces[pc] & self.sides[side];
+                while pieces > 0 {
+                    let sq: usize = lsb!(pieces) as usize;
+                    pieces &= pieces - 1;
+                    let attacks: u64 = match pc {
+                        KNIGHT => KNIGHT_ATTACKS[sq],
+                        BISHOP => bishop_attacks(sq, occ),
+                        ROOK => rook_attacks(sq, occ),
+                        _ => bishop_attacks(sq, occ) | rook_attacks(sq, occ),
+                    };
+                    let mobility: usize = (attacks & safe).count_ones() as usize;
+                    let (bonus, idx): (S, usize) = match pc {
+                        KNIGHT => (KNIGHT_MOBILITY[mobility], param::KNIGHT_MOBILITY),
+                        BISHOP => (BISHOP_MOBILITY[mobility], param::BISHOP_MOBILITY),
+                        ROOK => (ROOK_MOBILITY[mobility], param::ROOK_MOBILITY),
+                        _ => (QUEEN_MOBILITY[mobility], param::QUEEN_MOBILITY),
+                    };
+                    score += bonus;
+                    t.add(side, idx + mobility, 1);
+                    let zone_hits: i16 = (attacks & zone).count_ones() as i16;
+                    if zone_hits > 0 {
+                        units += ATTACK_UNITS[pc] * zone_hits;
+                        attackers += 1;
+                    }
+                    let rank: usize = if side == WHITE {sq >> 3} else {7 - (sq >> 3)};
+                    match pc {
+                        KNIGHT | BISHOP => {
+                            minor_attacks |= attacks;
+                            let outpost: bool = (3..=5).contains(&rank) && own_pawn_attacks & (1 << sq) > 0
+                                && opp_pawns & adjacent_files(sq) & forward_ranks(side, sq) == 0;
+                            if outpost {
+                                score += if pc == KNIGHT {KNIGHT_OUTPOST} else {BISHOP_OUTPOST};
+                                t.add(side, if pc == KNIGHT {param::KNIGHT_OUTPOST} else {param::BISHOP_OUTPOST}, 1);
+                            }
+                        }
+                        ROOK => {
+                            rook_attacks_bb |= attacks;
+                            let file: u64 = FILE << (sq & 7);
+                            if file & self.pieces[PAWN] == 0 {
+                                score += ROOK_OPEN;
+                                t.add(side, param::ROOK_OPEN, 1);
+                            } else if file & own_pawns == 0 {
+                                score += ROOK_SEMI_OPEN;
+                                t.add(side, param::ROOK_SEMI_OPEN, 1);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            // king safety
+            if attackers >= 2 {
+                let danger: S = S(std::cmp::min(i32::from(units).pow(2), i32::from(KING_DANGER_MAX)) as i16, units);
+                score += danger;
+                t.fixed(side, danger);
+            }
+            let own_king: usize = lsb!(self.pieces[KING] & self.sides[side]) as usize;
+            if (if side == WHITE {own_king >> 3} else {7 - (own_king >> 3)}) <= 1 {
+                let front: u64 = ((FILE << (own_king & 7)) | adjacent_files(own_king)) & forward_ranks(side, own_king);
+                let mut shield: u64 = own_pawns & front;
+                while shield > 0 {
+                    let sq: usize = lsb!(shield) as usize;
+                    shield &= shield - 1;
+                    let dist: usize = (sq >> 3).abs_diff(own_king >> 3);
+                    if dist < PAWN_SHIELD.len() {
+                        score += PAWN_SHIELD[dist];
+                        t.add(side, param::PAWN_SHIELD + dist, 1);
+                    }
+                }
+                let mut storm: u64 = opp_pawns & front;
+                while storm > 0 {
+                    let sq: usize = lsb!(storm) as usize;
+                    storm &= storm - 1;
+                    let rank: usize = if side == WHITE {sq >> 3} else {7 - (sq >> 3)};
+                    score += PAWN_STORM[rank];
+                    t.add(side, param::PAWN_STORM + rank, 1);
+                }
+            }
+
+            // bishop pair and threats
+            if (self.pieces[BISHOP] & self.sides[side]).count_ones() >= 2 {
+                score += BISHOP_PAIR;
+                t.add(side, param::BISHOP_PAIR, 1);
+            }
+            let opp_pieces: u64 = self.sides[opp] & !(self.pieces[PAWN] | self.pieces[KING]);
+            let opp_majors: u64 = self.sides[opp] & (self.pieces[ROOK] | self.pieces[QUEEN]);
+            let threats: [i16; 3] = [
+                (own_pawn_attacks & opp_pieces).count_ones() as i16,
+                (minor_attacks & opp_majors).count_ones() as i16,
+                (rook_attacks_bb & self.sides[opp] & self.pieces[QUEEN]).count_ones() as i16,
+            ];
+            score += threats[0] * THREAT_BY_PAWN + threats[1] * THREAT_BY_MINOR + threats[2] * THREAT_BY_ROOK;
+            t.add(side, param::THREAT_BY_PAWN, threats[0]);
+            t.add(side, param::THREAT_BY_MINOR, threats[1]);
+            t.add(side, param::THREAT_BY_ROOK, threats[2]);
+            total += SIDE_FACTOR[side] * score;
+        }
+        total
+    }
+}
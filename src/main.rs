@@ -3,9 +3,18 @@
 mod consts;
 mod position;
 mod movegen;
+mod picker;
 mod zobrist;
 mod tables;
 mod search;
+mod book;
+mod syzygy;
+mod dtm;
+mod endgame;
+mod eval;
+mod nnue;
+mod datagen;
+mod tune;
 
 use std::{io::stdin, time::Instant};
 use consts::*;
@@ -14,6 +23,9 @@ use position::{Position, State};
 use movegen::MoveList;
 use search::{go, SearchContext};
 use zobrist::ZVALS;
+use syzygy::Tablebases;
+use dtm::DtmTables;
+use datagen::Rng;
 
 macro_rules! parse {($type: ty, $s: expr, $else: expr) => {$s.parse::<$type>().unwrap_or($else)}}
 
@@ -21,6 +33,7 @@ fn main() {
     println!("{NAME}, created by {AUTHOR}");
     let mut pos: Position = parse_fen(STARTPOS);
     let mut ctx: SearchContext = SearchContext::new(HashTable::new(), KillerTable([[0; KILLERS_PER_PLY]; MAX_PLY as usize]));
+    let (mut hash_file, mut hash_file_depth): (String, i8) = (String::new(), 0);
     loop {
         let mut input = String::new();
         stdin().read_line(&mut input).unwrap();
@@ -30,32 +43,112 @@ fn main() {
                 println!("id name {NAME} {VERSION}");
                 println!("id author {AUTHOR}");
                 println!("option name UCI_Chess960 type check default false");
-                println!("option name Hash type spin default 128 min 1 max 512");
+                println!("option name Hash type spin default 128 min 1 max 65536");
                 println!("option name Clear Hash type button");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name DTMPath type string default <empty>");
+                println!("option name UseNNUE type check default false");
+                println!("option name QSearchChecks type check default false");
+                println!("option name Contempt type spin default 0 min -100 max 100");
+                println!("option name Analysis Contempt type check default false");
+                println!("option name UCI_AnalyseMode type check default false");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name HashFile type string default <empty>");
+                println!("option name HashFileMinDepth type spin default 0 min 0 max 64");
+                println!("option name Save Hash type button");
+                println!("option name Load Hash type button");
                 println!("uciok");
             }
             "isready" => println!("readyok"),
             "ucinewgame" => {
                 pos = parse_fen(STARTPOS);
                 ctx.hash_table.clear();
+                ctx.pawn_table.clear();
+                ctx.clear_history();
             },
             "setoption" => {
                 match commands[..] {
-                    ["setoption", "name", "Hash", "value", x] => ctx.hash_table.resize(parse!(usize, x, 1)),
+                    ["setoption", "name", "Hash", "value", x] => if let Err(err) = ctx.hash_table.resize(parse!(usize, x, 1)) {
+                        println!("info string failed to resize hash: {err}");
+                    },
                     ["setoption", "name", "Clear", "Hash"] => ctx.hash_table.clear(),
+                    ["setoption", "name", "SyzygyPath", "value", ref path @ ..] => ctx.tablebases = Tablebases::new(&path.join(" ")),
+                    ["setoption", "name", "DTMPath", "value", ref path @ ..] => ctx.dtm = DtmTables::new(&path.join(" ")),
+                    ["setoption", "name", "UseNNUE", "value", x] => ctx.use_nnue = x == "true",
+                    ["setoption", "name", "QSearchChecks", "value", x] => ctx.qsearch_checks = x == "true",
+                    ["setoption", "name", "Contempt", "value", x] => ctx.contempt = parse!(i16, x, 0).clamp(-100, 100),
+                    ["setoption", "name", "Analysis", "Contempt", "value", x] => ctx.analysis_contempt = x == "true",
+                    ["setoption", "name", "UCI_AnalyseMode", "value", x] => ctx.analyse_mode = x == "true",
+                    ["setoption", "name", "EvalFile", "value", ref path @ ..] => match nnue::load(&path.join(" ")) {
+                        Ok(()) => pos.refresh_accumulators(),
+                        Err(err) => println!("info string failed to load network: {err}"),
+                    },
+                    ["setoption", "name", "HashFile", "value", ref path @ ..] => hash_file = path.join(" "),
+                    ["setoption", "name", "HashFileMinDepth", "value", x] => hash_file_depth = parse!(i8, x, 0),
+                    ["setoption", "name", "Save", "Hash"] => save_hash(&ctx.hash_table, &hash_file, hash_file_depth),
+                    ["setoption", "name", "Load", "Hash"] => load_hash(&mut ctx.hash_table, &hash_file),
                     _ => {},
                 }
             },
             "go" => parse_go(&mut pos, commands, &mut ctx),
             "position" => parse_position(&mut pos, commands),
             "perft" => parse_perft(&mut pos, &commands),
+            "eval" => println!("info string nnue {} hce {}", pos.nnue_eval(), pos.eval(&mut ctx.pawn_table)),
+            "bench" => bench(&commands, &mut ctx),
             "perftsuite" => perft_suite(false),
             "frcsuite" => perft_suite(true),
+            "fuzz" => fuzz(&commands),
+            "makebook" => parse_makebook(&commands),
+            "gendtm" => parse_gendtm(&commands),
+            "datagen" => parse_datagen(&commands),
+            "tune" => parse_tune(&commands),
+            "savehash" => match commands.get(1) {
+                Some(path) => save_hash(&ctx.hash_table, path, commands.get(2).map_or(hash_file_depth, |x| parse!(i8, x, 0))),
+                None => println!("usage: savehash <file> [min depth]"),
+            },
+            "loadhash" => match commands.get(1) {
+                Some(path) => load_hash(&mut ctx.hash_table, path),
+                None => println!("usage: loadhash <file>"),
+            },
             _ => println!("unknown command"),
         }
     }
 }
 
+/// Fixed depth search over a set of positions, from a fresh hash table and histories.
+fn bench(commands: &[&str], ctx: &mut SearchContext) {
+    let depth: i8 = commands.get(1).map_or(BENCH_DEPTH, |x| parse!(i8, x, BENCH_DEPTH));
+    let timer: Instant = Instant::now();
+    let mut total: u64 = 0;
+    ctx.silent = true;
+    ctx.alloc_time = u128::MAX;
+    ctx.max_nodes = u64::MAX;
+    for fen in BENCH_POSITIONS {
+        let mut pos: Position = parse_fen(fen);
+        ctx.hash_table.clear();
+        ctx.clear_history();
+        go(&mut pos, depth, ctx);
+        total += ctx.nodes;
+    }
+    ctx.silent = false;
+    let secs: f64 = timer.elapsed().as_secs_f64();
+    println!("{total} nodes {:.0} nps", total as f64 / secs);
+}
+
+fn save_hash(hash_table: &HashTable, path: &str, min_depth: i8) {
+    match hash_table.save(path, min_depth) {
+        Ok(count) => println!("info string saved {count} hash entries"),
+        Err(err) => println!("info string failed to save hash: {err}"),
+    }
+}
+
+fn load_hash(hash_table: &mut HashTable, path: &str) {
+    match hash_table.load(path) {
+        Ok(count) => println!("info string loaded {count} hash entries"),
+        Err(err) => println!("info string failed to load hash: {err}"),
+    }
+}
+
 fn perft_suite(frc: bool) {
     let initial: Instant = Instant::now();
     let mut total: u64 = 0;
@@ -74,6 +167,48 @@ fn perft_suite(frc: bool) {
 
 }
 
+/// Random games from the perft positions, checking that `is_pseudo_legal` accepts
+/// exactly the moves given by `gen_moves`, out of every possible `u16`, and that
+/// `gen_moves::<CHECKS>` only gives quiets that check (or are illegal).
+fn fuzz(commands: &[&str]) {
+    let games: usize = commands.get(1).map_or(20, |x| parse!(usize, x, 20));
+    let timer: Instant = Instant::now();
+    let mut rng: Rng = Rng(0x9E37_79B9_7F4A_7C15);
+    let (mut positions, mut mismatches): (u64, u64) = (0, 0);
+    let mut generated: Vec<bool> = vec![false; 1 << 16];
+    for (fen, _, _) in POSITIONS.iter().chain(FRC_POSITIONS.iter()) {
+        for _ in 0..games {
+            let mut pos: Position = parse_fen(fen);
+            for _ in 0..FUZZ_PLIES {
+                let mut moves: MoveList = MoveList::default();
+                pos.gen_moves::<ALL>(&mut moves);
+                generated.fill(false);
+                moves.list[..moves.len].iter().for_each(|&m| generated[usize::from(m)] = true);
+                for m in 0..=u16::MAX {
+                    if pos.is_pseudo_legal(m) == generated[usize::from(m)] { continue }
+                    mismatches += 1;
+                    if mismatches <= 10 { println!("mismatch: {} move {m:#06x} generated {}", to_fen(&pos), generated[usize::from(m)]) }
+                }
+                let mut checks: MoveList = MoveList::default();
+                pos.gen_moves::<CHECKS>(&mut checks);
+                for &m in &checks.list[..checks.len] {
+                    let illegal: bool = pos.do_move(m);
+                    let ok: bool = illegal || pos.is_in_check();
+                    if !illegal { pos.undo_move() }
+                    if ok && generated[usize::from(m)] && m & 0xC000 == 0 { continue }
+                    mismatches += 1;
+                    if mismatches <= 10 { println!("bad check: {} move {m:#06x}", to_fen(&pos)) }
+                }
+                positions += 1;
+                let legal: Vec<u16> = datagen::legal_moves(&mut pos);
+                if legal.is_empty() { break }
+                pos.do_move(legal[rng.next() as usize % legal.len()]);
+            }
+        }
+    }
+    println!("positions {positions} mismatches {mismatches} time {}", timer.elapsed().as_millis());
+}
+
 fn perft(pos: &mut Position, depth_left: u8) -> u64 {
     let mut moves = MoveList::default();
     pos.gen_moves::<ALL>(&mut moves);
@@ -96,15 +231,64 @@ fn parse_perft(pos: &mut Position, commands: &[&str]) {
     }
 }
 
+fn parse_makebook(commands: &[&str]) {
+    if commands.len() < 3 {
+        println!("usage: makebook <pgn file> <output file> [max plies] [min games] [min score %]");
+        return
+    }
+    let plies: usize = parse!(usize, commands.get(3).unwrap_or(&""), 20);
+    let min_games: u32 = parse!(u32, commands.get(4).unwrap_or(&""), 3);
+    let min_score: u32 = parse!(u32, commands.get(5).unwrap_or(&""), 0);
+    if let Err(err) = book::make_book(commands[1], commands[2], plies, min_games, min_score) {
+        println!("info string makebook failed: {err}");
+    }
+}
+
+fn parse_gendtm(commands: &[&str]) {
+    if commands.len() < 3 {
+        println!("usage: gendtm <signature, e.g. KRvK> <output directory>");
+        return
+    }
+    if let Err(err) = dtm::generate(commands[1], commands[2]) {
+        println!("info string gendtm failed: {err}");
+    }
+}
+
+fn parse_datagen(commands: &[&str]) {
+    if commands.len() < 5 {
+        println!("usage: datagen <games> <threads> <nodes per move> <output path without extension> [frc]");
+        return
+    }
+    let games: usize = parse!(usize, commands[1], 1);
+    let threads: usize = std::cmp::max(parse!(usize, commands[2], 1), 1);
+    let nodes: u64 = parse!(u64, commands[3], 5000);
+    if let Err(err) = datagen::run(games, threads, nodes, commands[4], commands.get(5) == Some(&"frc")) {
+        println!("info string datagen failed: {err}");
+    }
+}
+
+fn parse_tune(commands: &[&str]) {
+    if commands.len() < 2 {
+        println!("usage: tune <dataset> [epochs] [output]");
+        return
+    }
+    let epochs: usize = commands.get(2).map_or(1000, |x| parse!(usize, x, 1000));
+    if let Err(err) = tune::run(commands[1], epochs, commands.get(3).copied()) {
+        println!("info string tuning failed: {err}");
+    }
+}
+
 fn parse_go(pos: &mut Position, commands: Vec<&str>, ctx: &mut SearchContext) {
-    enum Tokens {None, Depth, Movetime, WTime, BTime, WInc, BInc, MovesToGo}
+    enum Tokens {None, Depth, Movetime, Nodes, WTime, BTime, WInc, BInc, MovesToGo}
     let mut token: Tokens = Tokens::None;
     let (mut times, mut moves_to_go, mut depth): ([u64; 2], Option<u16>, i8) = ([0, 0], None, 64);
     ctx.alloc_time = 1000;
+    ctx.max_nodes = u64::MAX;
     for command in commands {
         match command {
             "depth" => token = Tokens::Depth,
             "movetime" => token = Tokens::Movetime,
+            "nodes" => token = Tokens::Nodes,
             "wtime" => token = Tokens::WTime,
             "btime" => token = Tokens::BTime,
             "winc" => token = Tokens::WInc,
@@ -114,6 +298,10 @@ fn parse_go(pos: &mut Position, commands: Vec<&str>, ctx: &mut SearchContext) {
                 match token {
                     Tokens::Depth => depth = std::cmp::min(parse!(i8, command, 1), 64),
                     Tokens::Movetime => ctx.alloc_time = parse!(i64, command, 1000) as u128 - 10,
+                    Tokens::Nodes => {
+                        ctx.max_nodes = parse!(u64, command, u64::MAX);
+                        ctx.alloc_time = u128::MAX;
+                    }
                     Tokens::WTime => times[0] = std::cmp::max(parse!(i64, command, 1000), 0) as u64,
                     Tokens::BTime => times[1] = std::cmp::max(parse!(i64, command, 1000), 0) as u64,
                     Tokens::MovesToGo => moves_to_go = Some(parse!(u16, command, 40)),
@@ -152,6 +340,37 @@ fn parse_position(pos: &mut Position, commands: Vec<&str>) {
 
 macro_rules! idx_to_sq {($idx:expr) => {format!("{}{}", char::from_u32(($idx & 7) as u32 + 97).unwrap(), ($idx >> 3) + 1)}}
 
+fn to_fen(pos: &Position) -> String {
+    let mut fen: String = String::new();
+    for rank in (0..8).rev() {
+        let mut empty: u8 = 0;
+        for file in 0..8 {
+            let sq: usize = 8 * rank + file;
+            let pc: usize = pos.squares[sq] as usize;
+            if pc == EMPTY {
+                empty += 1;
+                continue
+            }
+            if empty > 0 { fen.push((b'0' + empty) as char) }
+            empty = 0;
+            let ch: char = ['P','N','B','R','Q','K'][pc];
+            fen.push(if pos.sides[BLACK] & (1 << sq) > 0 {ch.to_ascii_lowercase()} else {ch});
+        }
+        if empty > 0 { fen.push((b'0' + empty) as char) }
+        if rank > 0 { fen.push('/') }
+    }
+    let rights: u8 = pos.state.castle_rights;
+    let mut castle: String = String::new();
+    for (right, side, i) in [(CastleRights::WHITE_KS, 0, 1), (CastleRights::WHITE_QS, 0, 0), (CastleRights::BLACK_KS, 1, 1), (CastleRights::BLACK_QS, 1, 0)] {
+        if rights & right == 0 { continue }
+        let ch: char = if pos.chess960 {(b'A' + pos.castle[i]) as char} else {['Q', 'K'][i]};
+        castle.push(if side == 1 {ch.to_ascii_lowercase()} else {ch});
+    }
+    if castle.is_empty() { castle.push('-') }
+    let enp: String = if pos.state.en_passant_sq > 0 {idx_to_sq!(pos.state.en_passant_sq)} else {"-".to_string()};
+    format!("{fen} {} {castle} {enp} {} {}", ["w", "b"][usize::from(pos.c)], pos.state.halfmove_clock, 1 + pos.stack.len() / 2)
+}
+
 /// Converts e.g. "a6" to index 5.
 fn sq_to_idx(sq: &str) -> u16 {
     let chs: Vec<char> = sq.chars().collect();
@@ -221,6 +440,7 @@ fn parse_fen(s: &str) -> Position {
                 pos.phase += PHASE_VALS[pc];
                 pos.state.scores += SIDE_FACTOR[side] * PST[pc][idx ^ (56 * usize::from(side == 0))];
                 pos.state.zobrist ^= ZVALS.pieces[side][pc][idx];
+                if pc == PAWN {pos.state.pawn_hash ^= ZVALS.pieces[side][pc][idx]}
                 idx -= usize::from(idx > 0);
             }
         }
@@ -283,5 +503,6 @@ fn parse_fen(s: &str) -> Position {
     pos.c = vec[1] == "b";
     if enp > 0 {pos.state.zobrist ^= ZVALS.en_passant[(enp & 7) as usize]}
     if !pos.c {pos.state.zobrist ^= ZVALS.side;}
+    pos.refresh_accumulators();
     pos
 }
\ No newline at end of file
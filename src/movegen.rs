@@ -42,23 +42,113 @@ fn btwn(bit1: u64, bit2: u64) -> u64 {
 }
 
 impl Position {
-    pub fn gen_moves<const QUIETS: bool>(&self, move_list: &mut MoveList) {
+    /// Generates pseudo-legal moves of the given type (`ALL`, `CAPTURES`, `QUIETS` or `CHECKS`),
+    /// where captures include en passant and capture promotions, and quiets include quiet promotions.
+    pub fn gen_moves<const MODE: u8>(&self, move_list: &mut MoveList) {
         let side: usize = usize::from(self.c);
         let occ: u64 = self.sides[0] | self.sides[1];
         let friendly: u64 = self.sides[side];
         let opps: u64 = self.sides[side ^ 1];
         let pawns: u64 = self.pieces[PAWN] & self.sides[side];
-        if QUIETS {
+        if MODE == CHECKS {
+            self.quiet_checks(move_list, occ, pawns);
+            return
+        }
+        if MODE != CAPTURES {
             if self.c {pawn_pushes::<BLACK>(move_list, occ, pawns)} else {pawn_pushes::<WHITE>(move_list, occ, pawns)}
             if self.state.castle_rights & CastleRights::SIDES[side] > 0 && !self.is_in_check() {self.castles(move_list, occ)}
         }
-        pawn_captures(move_list, pawns, opps, side);
-        if self.state.en_passant_sq > 0 {en_passants(move_list, pawns, self.state.en_passant_sq, side)}
-        piece_moves::<KNIGHT, QUIETS>(move_list, occ, friendly, opps, self.pieces[KNIGHT]);
-        piece_moves::<BISHOP, QUIETS>(move_list, occ, friendly, opps, self.pieces[BISHOP]);
-        piece_moves::<ROOK  , QUIETS>(move_list, occ, friendly, opps, self.pieces[ROOK]);
-        piece_moves::<QUEEN , QUIETS>(move_list, occ, friendly, opps, self.pieces[QUEEN]);
-        piece_moves::<KING  , QUIETS>(move_list, occ, friendly, opps, self.pieces[KING]);
+        if MODE != QUIETS {
+            pawn_captures(move_list, pawns, opps, side);
+            if self.state.en_passant_sq > 0 {en_passants(move_list, pawns, self.state.en_passant_sq, side)}
+        }
+        piece_moves::<KNIGHT, MODE>(move_list, occ, friendly, opps, self.pieces[KNIGHT]);
+        piece_moves::<BISHOP, MODE>(move_list, occ, friendly, opps, self.pieces[BISHOP]);
+        piece_moves::<ROOK  , MODE>(move_list, occ, friendly, opps, self.pieces[ROOK]);
+        piece_moves::<QUEEN , MODE>(move_list, occ, friendly, opps, self.pieces[QUEEN]);
+        piece_moves::<KING  , MODE>(move_list, occ, friendly, opps, self.pieces[KING]);
+    }
+
+    /// Whether an arbitrary move could have been generated by `gen_moves::<ALL>` in this
+    /// position, so that moves from the hash table or killers are safe to play.
+    pub fn is_pseudo_legal(&self, m: u16) -> bool {
+        let (from, to, flag): (usize, usize, u16) = (((m >> 6) & 63) as usize, (m & 63) as usize, m & MoveFlags::ALL);
+        let side: usize = usize::from(self.c);
+        let occ: u64 = self.sides[0] | self.sides[1];
+        let (f, t): (u64, u64) = (1 << from, 1 << to);
+        let pc: usize = self.squares[from] as usize;
+        if self.sides[side] & f == 0 { return false }
+        match flag {
+            MoveFlags::KS_CASTLE | MoveFlags::QS_CASTLE => {
+                if pc != KING || self.state.castle_rights & CastleRights::SIDES[side] == 0 || self.is_in_check() { return false }
+                let mut castles: MoveList = MoveList::default();
+                self.castles(&mut castles, occ);
+                castles.list[..castles.len].contains(&m)
+            }
+            MoveFlags::EN_PASSANT => pc == PAWN && self.state.en_passant_sq as usize == to && self.state.en_passant_sq > 0
+                && PAWN_ATTACKS[side][from] & t > 0,
+            // unused flags
+            0x6000 | 0x7000 => false,
+            _ => {
+                let capture: bool = flag & MoveFlags::CAPTURE > 0;
+                if (capture && self.sides[side ^ 1] & t == 0) || (!capture && occ & t > 0) { return false }
+                if pc != PAWN {
+                    return (flag == MoveFlags::QUIET || flag == MoveFlags::CAPTURE) && t & match pc {
+                        KNIGHT => KNIGHT_ATTACKS[from],
+                        BISHOP => bishop_attacks(from, occ),
+                        ROOK => rook_attacks(from, occ),
+                        QUEEN => rook_attacks(from, occ) | bishop_attacks(from, occ),
+                        _ => KING_ATTACKS[from],
+                    } > 0
+                }
+                // promotion flag iff moving to the back rank
+                if (flag & 0x8000 > 0) != (PENRANK[side] & f > 0) { return false }
+                if capture { return PAWN_ATTACKS[side][from] & t > 0 }
+                let push: usize = if side == WHITE {from + 8} else {from.wrapping_sub(8)};
+                if flag == MoveFlags::DBL_PUSH {
+                    DBLRANK[side] & t > 0 && to == if side == WHITE {push + 8} else {push.wrapping_sub(8)} && occ & (1 << push) == 0
+                } else { to == push }
+            }
+        }
+    }
+
+    /// Quiet moves that give direct check, not including promotions, castling or discovered checks.
+    fn quiet_checks(&self, move_list: &mut MoveList, occ: u64, pawns: u64) {
+        let side: usize = usize::from(self.c);
+        let ksq: usize = lsb!(self.pieces[KING] & self.sides[side ^ 1]) as usize;
+        let (diagonals, lines): (u64, u64) = (bishop_attacks(ksq, occ), rook_attacks(ksq, occ));
+        let mut pushes: MoveList = MoveList::default();
+        if self.c {pawn_pushes::<BLACK>(&mut pushes, occ, pawns & !PENRANK[side])} else {pawn_pushes::<WHITE>(&mut pushes, occ, pawns & !PENRANK[side])}
+        for &m in &pushes.list[..pushes.len] {
+            if PAWN_ATTACKS[side ^ 1][ksq] & (1 << (m & 63)) > 0 { move_list.push(m) }
+        }
+        for (piece, targets) in [(KNIGHT, KNIGHT_ATTACKS[ksq]), (BISHOP, diagonals), (ROOK, lines), (QUEEN, diagonals | lines)] {
+            let mut attackers: u64 = self.pieces[piece] & self.sides[side];
+            let mut from: u16;
+            while attackers > 0 {
+                pop_lsb!(from, attackers);
+                let idx: usize = from as usize;
+                let attacks: u64 = match piece {
+                    KNIGHT => KNIGHT_ATTACKS[idx],
+                    BISHOP => bishop_attacks(idx, occ),
+                    ROOK => rook_attacks(idx, occ),
+                    _ => rook_attacks(idx, occ) | bishop_attacks(idx, occ),
+                };
+                encode_moves(move_list, attacks & targets & !occ, from, MoveFlags::QUIET);
+            }
+        }
+    }
+
+    pub fn has_legal_moves(&mut self) -> bool {
+        let mut moves: MoveList = MoveList::default();
+        self.gen_moves::<ALL>(&mut moves);
+        for i in 0..moves.len {
+            if !self.do_move(moves.list[i]) {
+                self.undo_move();
+                return true
+            }
+        }
+        false
     }
 
     fn path(&self, mut path: u64, side: usize, occ: u64) -> bool {
@@ -99,7 +189,7 @@ impl Position {
     }
 }
 
-fn piece_moves<const PIECE: usize, const QUIETS: bool>(move_list: &mut MoveList, occ: u64, friendly: u64, opps: u64, mut attackers: u64) {
+fn piece_moves<const PIECE: usize, const MODE: u8>(move_list: &mut MoveList, occ: u64, friendly: u64, opps: u64, mut attackers: u64) {
     let mut from: u16;
     let mut idx: usize;
     let mut attacks: u64;
@@ -115,8 +205,8 @@ fn piece_moves<const PIECE: usize, const QUIETS: bool>(move_list: &mut MoveList,
             KING => KING_ATTACKS[idx],
             _ => 0,
         };
-        encode_moves(move_list, attacks & opps, from, MoveFlags::CAPTURE);
-        if QUIETS {encode_moves(move_list, attacks & !occ, from, MoveFlags::QUIET)}
+        if MODE != QUIETS {encode_moves(move_list, attacks & opps, from, MoveFlags::CAPTURE)}
+        if MODE != CAPTURES {encode_moves(move_list, attacks & !occ, from, MoveFlags::QUIET)}
     }
 }
 
@@ -162,13 +252,13 @@ pub fn rook_attacks(idx: usize, occ: u64) -> u64 {
     let m: Mask = RMASKS[idx];
     let mut f: u64 = occ & m.file;
     let mut r: u64 = f.swap_bytes();
-    f -= m.bit;
-    r -= m.bit.swap_bytes();
+    f = f.wrapping_sub(m.bit);
+    r = r.wrapping_sub(m.bit.swap_bytes());
     f ^= r.swap_bytes();
     f &= m.file;
     let mut e: u64 = m.right & occ;
     r = e & e.wrapping_neg();
-    e = (r ^ (r - m.bit)) & m.right;
+    e = (r ^ r.wrapping_sub(m.bit)) & m.right;
     let w: u64 = m.left ^ WEST[(((m.left & occ)| 1).leading_zeros() ^ 63) as usize];
 
     f | e | w
@@ -179,14 +269,14 @@ pub fn bishop_attacks(idx: usize, occ: u64) -> u64 {
     let m: Mask = BMASKS[idx];
     let mut f: u64 = occ & m.right;
     let mut r: u64 = f.swap_bytes();
-    f -= m.bit;
-    r -= m.file;
+    f = f.wrapping_sub(m.bit);
+    r = r.wrapping_sub(m.file);
     f ^= r.swap_bytes();
     f &= m.right;
     let mut f2: u64 = occ & m.left;
     r = f2.swap_bytes();
-    f2 -= m.bit;
-    r -= m.file;
+    f2 = f2.wrapping_sub(m.bit);
+    r = r.wrapping_sub(m.file);
     f2 ^= r.swap_bytes();
     f2 &= m.left;
 
@@ -226,3 +316,41 @@ fn pawn_pushes<const SIDE: usize>(move_list: &mut MoveList, occ: u64, pawns: u64
         move_list.push(MoveFlags::DBL_PUSH | idx_shift::<SIDE, 16>(idx) | idx << 6);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fen, to_fen, datagen::{Rng, legal_moves}};
+
+    #[test]
+    fn pseudo_legal_matches_gen_moves() {
+        let mut rng: Rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for (fen, _, _) in POSITIONS.iter().chain(FRC_POSITIONS.iter()) {
+            for _ in 0..4 {
+                let mut pos: Position = parse_fen(fen);
+                for _ in 0..FUZZ_PLIES {
+                    let mut moves: MoveList = MoveList::default();
+                    pos.gen_moves::<ALL>(&mut moves);
+                    let generated: &[u16] = &moves.list[..moves.len];
+                    for &m in generated {
+                        assert!(pos.is_pseudo_legal(m), "{} rejects generated move {m:#06x}", to_fen(&pos));
+                    }
+
+                    // random moves, and generated moves with a single bit flipped
+                    for _ in 0..512 {
+                        let r: u64 = rng.next();
+                        let m: u16 = if r & 1 == 0 || generated.is_empty() {(r >> 16) as u16} else {
+                            generated[(r >> 8) as usize % generated.len()] ^ (1 << ((r >> 32) % 16))
+                        };
+                        if generated.contains(&m) { continue }
+                        assert!(!pos.is_pseudo_legal(m), "{} accepts move {m:#06x}", to_fen(&pos));
+                    }
+
+                    let legal: Vec<u16> = legal_moves(&mut pos);
+                    if legal.is_empty() { break }
+                    pos.do_move(legal[rng.next() as usize % legal.len()]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicPtr, Ordering};
+use super::{consts::*, position::Position};
+
+pub const HIDDEN: usize = 32;
+const OUTPUT_BUCKETS: usize = 8;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+
+/// Hidden layer values for one perspective.
+#[derive(Clone, Copy)]
+#[repr(C, align(64))]
+pub struct Accumulator {
+    pub vals: [i16; HIDDEN],
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self { vals: [0; HIDDEN] }
+    }
+}
+
+impl Accumulator {
+    #[inline]
+    fn add(&mut self, weights: &Accumulator) {
+        self.vals.iter_mut().zip(weights.vals.iter()).for_each(|(v, &w)| *v += w);
+    }
+
+    #[inline]
+    fn sub(&mut self, weights: &Accumulator) {
+        self.vals.iter_mut().zip(weights.vals.iter()).for_each(|(v, &w)| *v -= w);
+    }
+}
+
+/// (768 -> HIDDEN)x2 -> 1 perspective network, with output buckets by piece count.
+/// The file format is this struct's in-memory layout, little endian.
+#[repr(C, align(64))]
+pub struct Network {
+    feature_weights: [Accumulator; 768],
+    feature_bias: Accumulator,
+    output_weights: [[Accumulator; 2]; OUTPUT_BUCKETS],
+    output_bias: [i16; OUTPUT_BUCKETS],
+}
+
+/// Default network, a quantised encoding of the tuned piece-square tables, so `UseNNUE`
+/// is off by default until a network trained on self-play data is loaded.
+static DEFAULT_NETWORK: Network = unsafe { std::mem::transmute(*include_bytes!("../resources/default.nnue")) };
+static NETWORK: AtomicPtr<Network> = AtomicPtr::new(&DEFAULT_NETWORK as *const Network as *mut Network);
+
+#[inline]
+fn network() -> &'static Network {
+    // only ever points to the default network or a leaked loaded one
+    unsafe { &*NETWORK.load(Ordering::Relaxed) }
+}
+
+/// Loads a network file to be used by all subsequent evaluations.
+pub fn load(path: &str) -> Result<(), String> {
+    let bytes: Vec<u8> = std::fs::read(path).map_err(|err| err.to_string())?;
+    if bytes.len() != std::mem::size_of::<Network>() {
+        return Err(format!("expected {} bytes, found {}", std::mem::size_of::<Network>(), bytes.len()))
+    }
+    let mut net: Box<Network> = Box::new(Network {
+        feature_weights: [Accumulator::default(); 768],
+        feature_bias: Accumulator::default(),
+        output_weights: [[Accumulator::default(); 2]; OUTPUT_BUCKETS],
+        output_bias: [0; OUTPUT_BUCKETS],
+    });
+    // Network is plain integers all the way down, so any bytes are valid
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), &mut *net as *mut Network as *mut u8, bytes.len()) }
+    NETWORK.store(Box::leak(net), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Input feature for a piece, from the given perspective.
+#[inline]
+fn feature(perspective: usize, side: usize, piece: usize, sq: usize) -> usize {
+    384 * usize::from(side != perspective) + 64 * piece + (sq ^ (56 * perspective))
+}
+
+/// Sum of clipped ReLU activations multiplied by output weights,
+/// using AVX2 when the cpu supports it (detected once, then cached by std).
+#[inline]
+fn crelu_dot(acc: &Accumulator, weights: &Accumulator) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // avx2 support was just checked
+        return unsafe { crelu_dot_avx2(acc, weights) }
+    }
+    acc.vals.iter().zip(weights.vals.iter()).map(|(&v, &w)| i32::from(v).clamp(0, QA) * i32::from(w)).sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn crelu_dot_avx2(acc: &Accumulator, weights: &Accumulator) -> i32 {
+    use std::arch::x86_64::*;
+    // Accumulator is 64 byte aligned and HIDDEN is a multiple of 16
+    let zero: __m256i = _mm256_setzero_si256();
+    let qa: __m256i = _mm256_set1_epi16(QA as i16);
+    let mut sum: __m256i = _mm256_setzero_si256();
+    for i in (0..HIDDEN).step_by(16) {
+        let v: __m256i = _mm256_load_si256(acc.vals.as_ptr().add(i) as *const __m256i);
+        let w: __m256i = _mm256_load_si256(weights.vals.as_ptr().add(i) as *const __m256i);
+        let clipped: __m256i = _mm256_min_epi16(_mm256_max_epi16(v, zero), qa);
+        sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, w));
+    }
+    let half: __m128i = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256(sum, 1));
+    let quarter: __m128i = _mm_add_epi32(half, _mm_shuffle_epi32(half, 0b0100_1110));
+    _mm_cvtsi128_si32(_mm_add_epi32(quarter, _mm_shuffle_epi32(quarter, 0b1011_0001)))
+}
+
+impl Position {
+    /// Adds or removes a piece from both accumulators.
+    #[inline]
+    pub fn update_accumulators(&mut self, side: usize, piece: usize, sq: usize, add: bool) {
+        let net: &Network = network();
+        for (perspective, acc) in self.state.accumulators.iter_mut().enumerate() {
+            let weights: &Accumulator = &net.feature_weights[feature(perspective, side, piece, sq)];
+            if add {acc.add(weights)} else {acc.sub(weights)}
+        }
+    }
+
+    /// Rebuilds both accumulators from scratch.
+    pub fn refresh_accumulators(&mut self) {
+        self.state.accumulators = [network().feature_bias; 2];
+        for sq in 0..64 {
+            let piece: usize = self.squares[sq] as usize;
+            if piece == EMPTY { continue }
+            let side: usize = usize::from(self.sides[BLACK] & (1 << sq) > 0);
+            self.update_accumulators(side, piece, sq, true);
+        }
+    }
+
+    /// Network evaluation, relative to the side to move.
+    pub fn nnue_eval(&self) -> i16 {
+        let net: &Network = network();
+        let stm: usize = usize::from(self.c);
+        let bucket: usize = ((self.sides[0] | self.sides[1]).count_ones() as usize - 2) / 4;
+        let weights: &[Accumulator; 2] = &net.output_weights[bucket];
+        let sum: i32 = crelu_dot(&self.state.accumulators[stm], &weights[0])
+            + crelu_dot(&self.state.accumulators[stm ^ 1], &weights[1])
+            + i32::from(net.output_bias[bucket]);
+        let eval: i16 = (sum * SCALE / (QA * QB)) as i16;
+        SIDE_FACTOR[stm] * self.endgame_eval(SIDE_FACTOR[stm] * eval)
+    }
+}
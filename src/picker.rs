@@ -0,0 +1,163 @@
+use super::{consts::*, position::Position, movegen::MoveList, search::SearchContext};
+
+/// Stages of move picking, in order.
+struct Stage;
+impl Stage {
+    const HASH_MOVE: u8 = 0;
+    const GEN_CAPTURES: u8 = 1;
+    const GOOD_CAPTURES: u8 = 2;
+    const KILLERS: u8 = 3;
+    const COUNTER_MOVE: u8 = 4;
+    const GEN_QUIETS: u8 = 5;
+    const QUIETS: u8 = 6;
+    const BAD_CAPTURES: u8 = 7;
+    const GEN_CHECKS: u8 = 8;
+    const CHECKS: u8 = 9;
+    const DONE: u8 = 10;
+}
+
+/// Lazily generates and orders moves, so that nothing is generated if the hash move causes a cutoff:
+/// 1. Hash move
+/// 2. Captures that don't lose material (SEE), by MVV-LVA
+/// 3. Killer moves
+/// 4. Countermove
+/// 5. Quiets, promotions first and then by history
+/// 6. Captures that lose material
+///
+/// In captures only mode (for quiescence search) every capture is returned by MVV-LVA,
+/// with the MVV-LVA value as its score, optionally followed by quiet checks with a score of zero.
+pub struct MovePicker {
+    stage: u8,
+    captures_only: bool,
+    checks: bool,
+    moves: MoveList,
+    scores: MoveList,
+    bad_captures: MoveList,
+    bad_scores: MoveList,
+    hash_move: u16,
+    killers: [u16; KILLERS_PER_PLY],
+    killer_idx: usize,
+    counter_move: u16,
+    prevs: [Option<(usize, usize)>; 2],
+}
+
+/// O(n^2) algorithm to incrementally sort the move list as needed.
+fn pick_move(moves: &mut MoveList, scores: &mut MoveList) -> Option<(u16, u16)> {
+    if scores.len == 0 {return None}
+    let mut idx: usize = 0;
+    let mut best: u16 = 0;
+    let mut score: u16;
+    for i in 0..scores.len {
+        score = scores.list[i];
+        if score > best {
+            best = score;
+            idx = i;
+        }
+    }
+    scores.len -= 1;
+    scores.list.swap(idx, scores.len);
+    moves.list.swap(idx, scores.len);
+    Some((moves.list[scores.len], best))
+}
+
+impl MovePicker {
+    /// The hash move must have been checked with `is_pseudo_legal`.
+    pub fn new(hash_move: u16, killers: [u16; KILLERS_PER_PLY], counter_move: u16, prevs: [Option<(usize, usize)>; 2]) -> Self {
+        Self {
+            stage: Stage::HASH_MOVE, captures_only: false, checks: false, moves: MoveList::default(), scores: MoveList::default(),
+            bad_captures: MoveList::default(), bad_scores: MoveList::default(), hash_move, killers, killer_idx: 0, counter_move, prevs,
+        }
+    }
+
+    pub fn captures(hash_move: u16, checks: bool) -> Self {
+        Self { captures_only: true, checks, ..Self::evasions(hash_move) }
+    }
+
+    /// Every move, without killers or history from previous plies.
+    pub fn evasions(hash_move: u16) -> Self {
+        Self::new(hash_move, [0; KILLERS_PER_PLY], 0, [None; 2])
+    }
+
+    /// Quiet move from a previous search that hasn't already been returned, and can be played here.
+    fn is_refutation(&self, pos: &Position, m: u16) -> bool {
+        m != 0 && m != self.hash_move && m & 0b1100_0000_0000_0000 == 0 && pos.is_pseudo_legal(m)
+    }
+
+    /// Next move to search and its ordering score, or `None` if there are no moves left.
+    pub fn next(&mut self, pos: &Position, ctx: &SearchContext) -> Option<(u16, u16)> {
+        loop {
+            match self.stage {
+                Stage::HASH_MOVE => {
+                    self.stage = Stage::GEN_CAPTURES;
+                    if self.hash_move != 0 { return Some((self.hash_move, if self.captures_only {pos.mvv_lva(self.hash_move)} else {HASH_MOVE})) }
+                }
+                Stage::GEN_CAPTURES => {
+                    pos.gen_moves::<CAPTURES>(&mut self.moves);
+                    for i in 0..self.moves.len { self.scores.push(pos.mvv_lva(self.moves.list[i])) }
+                    self.stage = Stage::GOOD_CAPTURES;
+                }
+                Stage::GOOD_CAPTURES => match pick_move(&mut self.moves, &mut self.scores) {
+                    Some((m, _)) if m == self.hash_move => {}
+                    Some((m, score)) if self.captures_only => return Some((m, score)),
+                    Some((m, score)) if !pos.see(m, 0) => {
+                        self.bad_captures.push(m);
+                        self.bad_scores.push(score);
+                    }
+                    Some((m, score)) => return Some((m, CAPTURE + score)),
+                    None => self.stage = match (self.captures_only, self.checks) {
+                        (false, _) => Stage::KILLERS,
+                        (true, true) => Stage::GEN_CHECKS,
+                        (true, false) => Stage::DONE,
+                    },
+                }
+                Stage::KILLERS => {
+                    if self.killer_idx == KILLERS_PER_PLY {
+                        self.stage = Stage::COUNTER_MOVE;
+                        continue
+                    }
+                    let m: u16 = self.killers[self.killer_idx];
+                    self.killer_idx += 1;
+                    if !self.killers[..self.killer_idx - 1].contains(&m) && self.is_refutation(pos, m) { return Some((m, KILLER)) }
+                }
+                Stage::COUNTER_MOVE => {
+                    self.stage = Stage::GEN_QUIETS;
+                    let m: u16 = self.counter_move;
+                    if !self.killers.contains(&m) && self.is_refutation(pos, m) { return Some((m, COUNTER_MOVE)) }
+                }
+                Stage::GEN_QUIETS => {
+                    let mut quiets: MoveList = MoveList::default();
+                    pos.gen_moves::<QUIETS>(&mut quiets);
+                    self.moves.len = 0;
+                    for &m in &quiets.list[..quiets.len] {
+                        if m == self.hash_move || m == self.counter_move || self.killers.contains(&m) { continue }
+                        self.moves.push(m);
+                        self.scores.push(if m & 0b1000_0000_0000_0000 > 0 {PROMOTION} else {(i32::from(QUIET) + ctx.quiet_history(pos, m, &self.prevs)) as u16});
+                    }
+                    self.stage = Stage::QUIETS;
+                }
+                Stage::QUIETS => match pick_move(&mut self.moves, &mut self.scores) {
+                    Some(res) => return Some(res),
+                    None => self.stage = Stage::BAD_CAPTURES,
+                }
+                Stage::BAD_CAPTURES => match pick_move(&mut self.bad_captures, &mut self.bad_scores) {
+                    Some((m, score)) => return Some((m, BAD_CAPTURE + score)),
+                    None => self.stage = Stage::DONE,
+                }
+                Stage::GEN_CHECKS => {
+                    self.moves.len = 0;
+                    pos.gen_moves::<CHECKS>(&mut self.moves);
+                    self.stage = Stage::CHECKS;
+                }
+                Stage::CHECKS => {
+                    if self.moves.len == 0 {
+                        self.stage = Stage::DONE;
+                        continue
+                    }
+                    self.moves.len -= 1;
+                    return Some((self.moves.list[self.moves.len], 0))
+                }
+                _ => return None,
+            }
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use std::ops::{AddAssign, Mul};
-use super::{lsb, consts::*, movegen::{bishop_attacks, rook_attacks}, zobrist::ZVALS};
+use std::ops::{Add, AddAssign, Mul};
+use super::{lsb, consts::*, movegen::{bishop_attacks, rook_attacks}, zobrist::{ZVALS, CUCKOO}, nnue::Accumulator};
 
 macro_rules! from {($m:expr) => {(($m >> 6) & 63) as usize}}
 macro_rules! to {($m:expr) => {($m & 63) as usize}}
@@ -10,8 +10,8 @@ macro_rules! pop {($x:expr) => {$x &= $x - 1}}
 /// - Holds all information needed for the board state
 /// - 6 piece bitboards and 2 colour bitboards
 /// - Mailbox array for finding pieces quickly
-/// - Incrementally updated zobrist hash, phase and endgame and midgame
-/// piece-square table scores
+/// - Incrementally updated zobrist hash, pawn hash, phase, endgame and midgame
+///   piece-square table scores and network accumulators
 pub struct Position {
     pub pieces: [u64; 6],
     pub sides: [u64; 2],
@@ -29,10 +29,12 @@ pub struct Position {
 #[derive(Clone, Copy, Default)]
 pub struct State {
     pub zobrist: u64,
+    pub pawn_hash: u64,
     pub scores: S,
     pub en_passant_sq: u16,
     pub halfmove_clock: u8,
     pub castle_rights: u8,
+    pub accumulators: [Accumulator; 2],
 }
 
 #[derive(Clone, Copy)]
@@ -53,6 +55,13 @@ impl AddAssign<S> for S {
     }
 }
 
+impl Add<S> for S {
+    type Output = S;
+    fn add(self, rhs: S) -> Self::Output {
+        S(self.0 + rhs.0, self.1 + rhs.1)
+    }
+}
+
 impl Mul<S> for i16 {
     type Output = S;
     fn mul(self, rhs: S) -> Self::Output {
@@ -77,6 +86,39 @@ impl Position {
         self.is_square_attacked(king_idx, usize::from(self.c), self.sides[0] | self.sides[1])
     }
 
+    /// Whether a pseudo-legal move would give check, directly or by discovery, without making it.
+    pub fn gives_check(&self, m: u16) -> bool {
+        let (from, to, flag): (usize, usize, u16) = (from!(m), to!(m), m & MoveFlags::ALL);
+        let side: usize = usize::from(self.c);
+        let ksq: usize = lsb!(self.pieces[KING] & self.sides[side ^ 1]) as usize;
+        let mut vacated: u64 = bit!(from);
+        let (mut piece, mut sq): (usize, usize) = (self.squares[from] as usize, to);
+        match flag {
+            MoveFlags::EN_PASSANT => vacated |= bit!(if side == BLACK {to + 8} else {to - 8}),
+            MoveFlags::KS_CASTLE | MoveFlags::QS_CASTLE => {
+                let i: usize = usize::from(flag == MoveFlags::KS_CASTLE);
+                vacated |= bit!(56 * side + self.castle[i] as usize);
+                (piece, sq) = (ROOK, CASTLE_MOVES[side][i]);
+            }
+            MoveFlags::KNIGHT_PROMO.. => piece = (((flag >> 12) & 3) + 1) as usize,
+            _ => {}
+        }
+        let occ: u64 = (self.sides[0] | self.sides[1]) & !vacated | bit!(to) | bit!(sq);
+        let direct: u64 = match piece {
+            PAWN => PAWN_ATTACKS[side][sq],
+            KNIGHT => KNIGHT_ATTACKS[sq],
+            BISHOP => bishop_attacks(sq, occ),
+            ROOK => rook_attacks(sq, occ),
+            QUEEN => rook_attacks(sq, occ) | bishop_attacks(sq, occ),
+            _ => 0,
+        };
+        let ours: u64 = self.sides[side] & !vacated;
+        let queens: u64 = self.pieces[QUEEN] & ours;
+        direct & bit!(ksq) > 0
+        || rook_attacks(ksq, occ) & (self.pieces[ROOK] & ours | queens) > 0
+        || bishop_attacks(ksq, occ) & (self.pieces[BISHOP] & ours | queens) > 0
+    }
+
     #[inline(always)]
     fn toggle(&mut self, side: usize, piece: usize, bit: u64) {
         self.pieces[piece] ^= bit;
@@ -87,14 +129,18 @@ impl Position {
     fn add(&mut self, from: usize, side: usize, piece: usize) {
         let indx = from ^ (56 * (side == 0) as usize);
         self.state.zobrist ^= ZVALS.pieces[side][piece][from];
+        if piece == PAWN {self.state.pawn_hash ^= ZVALS.pieces[side][PAWN][from]}
         self.state.scores += SIDE_FACTOR[side] * PST[piece][indx];
+        self.update_accumulators(side, piece, from, true);
     }
 
     #[inline(always)]
     fn remove(&mut self, from: usize, side: usize, piece: usize) {
         let indx = from ^ (56 * (side == 0) as usize);
         self.state.zobrist ^= ZVALS.pieces[side][piece][from];
+        if piece == PAWN {self.state.pawn_hash ^= ZVALS.pieces[side][PAWN][from]}
         self.state.scores += SIDE_FACTOR[side ^ 1] * PST[piece][indx];
+        self.update_accumulators(side, piece, from, false);
     }
 
     pub fn do_move(&mut self, m: u16) -> bool {
@@ -253,6 +299,24 @@ impl Position {
         false
     }
 
+    /// Whether the side to move has a reversible move back to a position from within the last
+    /// `ply` plies (i.e. the search), so that a draw can be forced before it is on the board.
+    pub fn upcoming_repetition(&self, ply: i16) -> bool {
+        let l: usize = self.stack.len();
+        let end: usize = std::cmp::min(self.state.halfmove_clock as usize, l);
+        if end < 3 || self.nulls > 0 { return false }
+        let occ: u64 = self.sides[0] | self.sides[1];
+        for i in (3..=end).step_by(2) {
+            if i >= ply as usize { break }
+            let Some(m) = CUCKOO.probe(self.state.zobrist ^ self.stack[l - i].state.zobrist) else { continue };
+            let (s1, s2): (usize, usize) = (from!(m), to!(m));
+            let sq: usize = if occ & bit!(s1) > 0 {s1} else {s2};
+            let clear: bool = KNIGHT_ATTACKS[s1] & bit!(s2) > 0 || (rook_attacks(s1, occ) | bishop_attacks(s1, occ)) & bit!(s2) > 0;
+            if clear && self.sides[usize::from(self.c)] & bit!(sq) > 0 { return true }
+        }
+        false
+    }
+
     pub fn material_draw(&self) -> bool {
         let pawns: u64 = self.pieces[PAWN];
         if pawns == 0 && self.phase <= 2 {
@@ -265,9 +329,157 @@ impl Position {
         false
     }
 
+    /// Moving piece and destination square of the move played the given number of plies ago, if any.
+    pub fn prev_move(&self, ago: usize) -> Option<(usize, usize)> {
+        self.stack.len().checked_sub(ago).map(|i| (self.stack[i].moved_pc as usize, to!(self.stack[i].m)))
+    }
+
+    /// Moving piece and destination square of a move in this position.
+    pub fn piece_and_to(&self, m: u16) -> (usize, usize) {
+        (self.squares[from!(m)] as usize, to!(m))
+    }
+
+    /// All pieces of either side attacking a square, given an occupancy.
+    fn attackers_to(&self, sq: usize, occ: u64) -> u64 {
+        let queens: u64 = self.pieces[QUEEN];
+        (KNIGHT_ATTACKS[sq] & self.pieces[KNIGHT])
+        | (KING_ATTACKS[sq] & self.pieces[KING])
+        | (PAWN_ATTACKS[WHITE][sq] & self.pieces[PAWN] & self.sides[BLACK])
+        | (PAWN_ATTACKS[BLACK][sq] & self.pieces[PAWN] & self.sides[WHITE])
+        | (rook_attacks(sq, occ) & (self.pieces[ROOK] | queens))
+        | (bishop_attacks(sq, occ) & (self.pieces[BISHOP] | queens))
+    }
+
+    /// Static exchange evaluation, whether the move wins at least `threshold` material
+    /// once all captures on the destination square have been resolved.
+    pub fn see(&self, m: u16, threshold: i16) -> bool {
+        let (from, to): (usize, usize) = (from!(m), to!(m));
+        let flag: u16 = m & MoveFlags::ALL;
+        if flag == MoveFlags::KS_CASTLE || flag == MoveFlags::QS_CASTLE { return threshold <= 0 }
+
+        // gain from the move itself
+        let mut next: usize = self.squares[from] as usize;
+        let mut score: i16 = -threshold;
+        score += if flag == MoveFlags::EN_PASSANT {SEE_VALS[PAWN]} else {SEE_VALS[self.squares[to] as usize]};
+        if flag >= MoveFlags::KNIGHT_PROMO {
+            next = (((flag >> 12) & 3) + 1) as usize;
+            score += SEE_VALS[next] - SEE_VALS[PAWN];
+        }
+        if score < 0 { return false }
+
+        // worst case, the moved piece is lost for nothing
+        score -= SEE_VALS[next];
+        if score >= 0 { return true }
+
+        let mut occ: u64 = (self.sides[0] | self.sides[1]) ^ bit!(from) ^ bit!(to);
+        if flag == MoveFlags::EN_PASSANT { occ ^= bit!(if self.c {to + 8} else {to - 8}) }
+        let bishops: u64 = self.pieces[BISHOP] | self.pieces[QUEEN];
+        let rooks: u64 = self.pieces[ROOK] | self.pieces[QUEEN];
+        let mut attackers: u64 = self.attackers_to(to, occ) & occ;
+        let mut side: usize = usize::from(!self.c);
+        loop {
+            let own: u64 = attackers & self.sides[side];
+            if own == 0 { break }
+
+            // capture with the least valuable attacker, revealing any x-rays behind it
+            next = PAWN;
+            while own & self.pieces[next] == 0 { next += 1 }
+            let pc: u64 = own & self.pieces[next];
+            occ ^= pc & pc.wrapping_neg();
+            if next == PAWN || next == BISHOP || next == QUEEN { attackers |= bishop_attacks(to, occ) & bishops }
+            if next == ROOK || next == QUEEN { attackers |= rook_attacks(to, occ) & rooks }
+            attackers &= occ;
+
+            score = -score - 1 - SEE_VALS[next];
+            side ^= 1;
+            if score >= 0 {
+                // capturing with the king into a defended square isn't legal
+                if next == KING && attackers & self.sides[side] > 0 { side ^= 1 }
+                break
+            }
+        }
+        side != usize::from(self.c)
+    }
+
     pub fn mvv_lva(&self, m: u16) -> u16 {
         let moved_pc: usize = self.squares[from!(m)] as usize;
         let captured_pc: usize = self.squares[to!(m)] as usize;
         MVV_LVA[captured_pc][moved_pc]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fen, to_fen, uci_to_u16, movegen::MoveList, datagen::{Rng, legal_moves}};
+
+    #[test]
+    fn gives_check_matches_do_move() {
+        let mut rng: Rng = Rng(0x2545_F491_4F6C_DD1D);
+        for (fen, _, _) in POSITIONS.iter().chain(FRC_POSITIONS.iter()) {
+            for _ in 0..4 {
+                let mut pos: Position = parse_fen(fen);
+                for _ in 0..FUZZ_PLIES {
+                    let mut moves: MoveList = MoveList::default();
+                    pos.gen_moves::<ALL>(&mut moves);
+                    for &m in &moves.list[..moves.len] {
+                        let expected: bool = pos.gives_check(m);
+                        if pos.do_move(m) { continue }
+                        assert_eq!(pos.is_in_check(), expected, "{} move {m:#06x}", to_fen(&pos));
+                        pos.undo_move();
+                    }
+
+                    let legal: Vec<u16> = legal_moves(&mut pos);
+                    if legal.is_empty() { break }
+                    pos.do_move(legal[rng.next() as usize % legal.len()]);
+                }
+            }
+        }
+    }
+
+    /// The pawn hash (and full key) kept up to date by making and unmaking moves
+    /// is the one computed from scratch for the same position.
+    #[test]
+    fn incremental_hashes_match_recomputed() {
+        let mut rng: Rng = Rng(0x5851_F42D_4C95_7F2D);
+        for (fen, _, _) in POSITIONS.iter().chain(FRC_POSITIONS.iter()) {
+            for _ in 0..4 {
+                let mut pos: Position = parse_fen(fen);
+                for _ in 0..FUZZ_PLIES {
+                    let fresh: Position = parse_fen(&to_fen(&pos));
+                    assert_eq!(pos.state.pawn_hash, fresh.state.pawn_hash, "{}", to_fen(&pos));
+                    assert_eq!(pos.state.zobrist, fresh.state.zobrist, "{}", to_fen(&pos));
+
+                    let legal: Vec<u16> = legal_moves(&mut pos);
+                    if legal.is_empty() { break }
+                    let (pawn_hash, zobrist): (u64, u64) = (pos.state.pawn_hash, pos.state.zobrist);
+                    for &m in &legal {
+                        pos.do_move(m);
+                        pos.undo_move();
+                        assert_eq!((pos.state.pawn_hash, pos.state.zobrist), (pawn_hash, zobrist));
+                    }
+                    pos.do_move(legal[rng.next() as usize % legal.len()]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detects_upcoming_repetitions() {
+        let play = |fen: &str, moves: &[&str]| -> Position {
+            let mut pos: Position = parse_fen(fen);
+            for uci in moves { pos.do_move(uci_to_u16(&pos, uci)); }
+            pos
+        };
+
+        // black can return the knight to the starting position, but only once it is inside the search
+        let pos: Position = play(STARTPOS, &["g1f3", "g8f6", "f3g1"]);
+        assert!(pos.upcoming_repetition(4));
+        assert!(!pos.upcoming_repetition(3));
+
+        // the rook took a detour, so can only go straight back if the a-file is clear
+        let detour: [&str; 7] = ["h8g8", "a1b1", "g8g7", "b1b5", "g7h7", "b5a5", "h7h8"];
+        assert!(play("7k/8/8/8/8/8/8/R6K b - - 0 1", &detour).upcoming_repetition(8));
+        assert!(!play("7k/8/8/8/8/P7/8/R6K b - - 0 1", &detour).upcoming_repetition(8));
+    }
+}
@@ -1,126 +1,193 @@
-use super::{consts::*, position::Position, tables::{HashTable, KillerTable}, movegen::MoveList,u16_to_uci};
+use super::{consts::*, position::Position, tables::{HashEntry, HashTable, KillerTable, PvTable, PawnHashTable, HistoryTable, CounterMoveTable, ContinuationTable}, movegen::MoveList, picker::MovePicker, syzygy::Tablebases, dtm::DtmTables, u16_to_uci};
 use std::{cmp::{min, max}, time::Instant};
 
 /// Determines what is done in the node
 struct NodeType(u8);
 impl NodeType {
-    fn encode(pv: bool, check: bool, null: bool) -> Self {
-        Self(4 * u8::from(pv) + 2 * u8::from(check) + u8::from(null))
+    fn encode(pv: bool, check: bool, null: bool, cut: bool) -> Self {
+        Self(8 * u8::from(cut) + 4 * u8::from(pv) + 2 * u8::from(check) + u8::from(null))
     }
 }
 
+/// Information about a ply of the line currently being searched.
+#[derive(Clone, Copy, Default)]
+struct StackEntry {
+    eval: i16,
+    /// move being searched from this ply, zero for a null move
+    m: u16,
+    piece: usize,
+    /// move skipped when verifying a singular extension
+    excluded: u16,
+    double_exts: u8,
+    in_check: bool,
+}
+
 /// Contains everything needed for a search.
 pub struct SearchContext {
     pub hash_table: HashTable,
     killer_table: KillerTable,
+    history: HistoryTable,
+    counter_moves: CounterMoveTable,
+    continuation: [ContinuationTable; 2],
+    lmr_table: [[i8; 64]; 64],
+    stack: [StackEntry; MAX_PLY as usize],
+    root_prevs: [Option<(usize, usize)>; 2],
+    pv_table: PvTable,
+    pub pawn_table: PawnHashTable,
+    pub use_nnue: bool,
+    pub qsearch_checks: bool,
+    pub contempt: i16,
+    pub analysis_contempt: bool,
+    pub analyse_mode: bool,
+    root_side: bool,
+    pub tablebases: Tablebases,
+    pub dtm: DtmTables,
     pub alloc_time: u128,
+    pub max_nodes: u64,
+    pub silent: bool,
     time: Instant,
-    nodes: u64,
+    pub nodes: u64,
+    tb_hits: u64,
     ply: i16,
     abort: bool,
+    can_abort: bool,
+}
+
+/// Base late move reductions, by depth and move number.
+fn lmr_table() -> [[i8; 64]; 64] {
+    let mut table: [[i8; 64]; 64] = [[0; 64]; 64];
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (moves, r) in row.iter_mut().enumerate().skip(1) {
+            *r = (LMR_BASE + (depth as f64).ln() * (moves as f64).ln() / LMR_DIVISOR) as i8;
+        }
+    }
+    table
 }
 
 impl SearchContext{
     pub fn new(hash_table: HashTable, killer_table: KillerTable) -> Self {
-        Self { hash_table, killer_table, time: Instant::now(), alloc_time: 1000, nodes: 0, ply: 0, abort: false }
+        Self { hash_table, killer_table, history: HistoryTable::new(), counter_moves: CounterMoveTable::new(), continuation: [ContinuationTable::new(), ContinuationTable::new()], lmr_table: lmr_table(), stack: [StackEntry::default(); MAX_PLY as usize], root_prevs: [None; 2], pv_table: PvTable::new(), pawn_table: PawnHashTable::new(), use_nnue: false, qsearch_checks: false, contempt: 0, analysis_contempt: false, analyse_mode: false, root_side: false, tablebases: Tablebases::default(), dtm: DtmTables::default(), time: Instant::now(), alloc_time: 1000, max_nodes: u64::MAX, silent: false, nodes: 0, tb_hits: 0, ply: 0, abort: false, can_abort: false }
     }
 
-    fn reset(&mut self) {
-        self.time = Instant::now();
-        self.nodes = 0;
-        self.ply = 0;
-        self.abort = false;
+    /// Static evaluation of the position, relative to the side to move.
+    fn eval(&mut self, pos: &Position) -> i16 {
+        if self.use_nnue {pos.nnue_eval()} else {pos.eval(&mut self.pawn_table)}
     }
-}
 
-impl Position {
-    #[inline]
-    fn lazy_eval(&self) -> i16 {
-        let phase: i32 = std::cmp::min(self.phase as i32, TPHASE);
-        SIDE_FACTOR[usize::from(self.c)] * ((phase * self.state.scores.0 as i32 + (TPHASE - phase) * self.state.scores.1 as i32) / TPHASE) as i16
+    /// Score of a draw for the side to move, `contempt` worse for the side to move at the root
+    /// (unless analysing without `analysis_contempt`), and randomly one either side of that
+    /// so that the search doesn't fall into repetitions it can't tell apart.
+    fn draw_score(&self, pos: &Position) -> i16 {
+        let contempt: i16 = if self.analyse_mode && !self.analysis_contempt {0} else {self.contempt};
+        let sign: i16 = if pos.c == self.root_side {-1} else {1};
+        sign * contempt - 1 + 2 * (self.nodes & 1) as i16
     }
 
-    fn score_move(&self, m: u16, hash_move: u16, killers: &[u16; KILLERS_PER_PLY]) -> u16 {
-        if m == hash_move {
-            HASH_MOVE
-        } else if m & 0b0100_0000_0000_0000 > 0 {
-            self.mvv_lva(m)
-        } else if m & 0b1000_0000_0000_0000 > 0 {
-            PROMOTION
-        } else if killers.contains(&m) {
-            KILLER
-        } else {
-            QUIET
+    /// Piece and destination of the moves played one and two plies before the given ply, if not null moves,
+    /// going back into the game's moves before the root.
+    fn prev_moves(&self, ply: usize) -> [Option<(usize, usize)>; 2] {
+        [1, 2].map(|ago| match ply.checked_sub(ago) {
+            Some(p) => Some(self.stack[p]).filter(|e| e.m != 0).map(|e| (e.piece, (e.m & 63) as usize)),
+            None => self.root_prevs[ago - ply - 1],
+        })
+    }
+
+    /// Sum of butterfly and 1/2-ply continuation history for a quiet move.
+    pub fn quiet_history(&self, pos: &Position, m: u16, prevs: &[Option<(usize, usize)>; 2]) -> i32 {
+        let side: usize = usize::from(pos.c);
+        let key: (usize, usize) = pos.piece_and_to(m);
+        let mut score: i32 = i32::from(self.history.get(side, m));
+        for (table, prev) in self.continuation.iter().zip(prevs) {
+            if let Some(prev) = *prev { score += i32::from(table.get(side, prev, key)) }
         }
+        score
     }
 
-    fn score_moves(&self, moves: &MoveList, move_scores: &mut MoveList, hash_move: u16, ply: i16, kt: &KillerTable) {
-        let killers: [u16; KILLERS_PER_PLY] = kt.0[ply as usize];
-        for i in 0..moves.len { move_scores.push(self.score_move(moves.list[i], hash_move, &killers)) }
+    /// Rewards the quiet move that caused a beta cutoff, and punishes the quiets searched before it.
+    fn update_quiet_history(&mut self, pos: &Position, ply: usize, best_move: u16, quiets: &MoveList, depth: i8) {
+        let side: usize = usize::from(pos.c);
+        let bonus: i16 = min(16 * i32::from(depth).pow(2), i32::from(HISTORY_BONUS_MAX)) as i16;
+        let prevs: [Option<(usize, usize)>; 2] = self.prev_moves(ply);
+        for &m in &quiets.list[..quiets.len] {
+            let delta: i16 = if m == best_move {bonus} else {-bonus};
+            let key: (usize, usize) = pos.piece_and_to(m);
+            self.history.update(side, m, delta);
+            for (table, prev) in self.continuation.iter_mut().zip(prevs) {
+                if let Some(prev) = prev { table.update(side, prev, key, delta) }
+            }
+        }
+        if let Some(prev) = prevs[0] { self.counter_moves.push(side, prev, best_move) }
     }
 
-    fn score_captures(&self, moves: &MoveList, move_scores: &mut MoveList) {
-        for i in 0..moves.len { move_scores.push(self.mvv_lva(moves.list[i])) }
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.counter_moves.clear();
+        self.continuation.iter_mut().for_each(ContinuationTable::clear);
     }
-}
 
-/// O(n^2) algorithm to incrementally sort the move list as needed.
-fn pick_move(moves: &mut MoveList, scores: &mut MoveList) -> Option<(u16, u16)> {
-    if scores.len == 0 {return None}
-    let mut idx: usize = 0;
-    let mut best: u16 = 0;
-    let mut score: u16;
-    for i in 0..scores.len {
-        score = scores.list[i];
-        if score > best {
-            best = score;
-            idx = i;
-        }
+    fn reset(&mut self) {
+        self.time = Instant::now();
+        self.nodes = 0;
+        self.tb_hits = 0;
+        self.ply = 0;
+        self.abort = false;
+        self.stack = [StackEntry::default(); MAX_PLY as usize];
     }
-    scores.len -= 1;
-    scores.list.swap(idx, scores.len);
-    moves.list.swap(idx, scores.len);
-    Some((moves.list[scores.len], best))
 }
 
 /// Main search function:
 /// - Fail-soft negamax (alpha-beta pruning) framework
 /// - Principle variation search
-fn search(pos: &mut Position, nt: NodeType, mut alpha: i16, mut beta: i16, mut depth: i8, ctx: &mut SearchContext, pv_line: &mut Vec<u16>) -> i16 {
+fn search(pos: &mut Position, nt: NodeType, mut alpha: i16, mut beta: i16, mut depth: i8, ctx: &mut SearchContext) -> i16 {
+    ctx.pv_table.clear(ctx.ply as usize);
+
     // search aborting
     if ctx.abort { return 0 }
-    if ctx.nodes & 2047 == 0 && ctx.time.elapsed().as_millis() >= ctx.alloc_time {
+    if ctx.can_abort && (ctx.nodes >= ctx.max_nodes || (ctx.nodes & 2047 == 0 && ctx.time.elapsed().as_millis() >= ctx.alloc_time)) {
         ctx.abort = true;
         return 0
     }
 
     // draw detection
-    if pos.fifty_draw() || pos.repetition_draw(2 + u8::from(ctx.ply == 0)) || pos.material_draw() { return 0 }
+    if pos.fifty_draw() || pos.repetition_draw(2 + u8::from(ctx.ply == 0)) || pos.material_draw() { return ctx.draw_score(pos) }
+
+    // extensions can't go on forever
+    if ctx.ply >= i16::from(MAX_PLY) - 1 { return ctx.eval(pos) }
 
     // extract node info
-    let (pv, in_check, allow_null): (bool, bool, bool) = (nt.0 & 4 > 0, nt.0 & 2 > 0, nt.0 & 1 > 0);
+    let (pv, in_check, allow_null, cut_node): (bool, bool, bool, bool) = (nt.0 & 4 > 0, nt.0 & 2 > 0, nt.0 & 1 > 0, nt.0 & 8 > 0);
+    let ply: usize = ctx.ply as usize;
+    let excluded: u16 = ctx.stack[ply].excluded;
 
     // mate distance pruning
     alpha = max(alpha, -MAX + ctx.ply);
     beta = min(beta, MAX - ctx.ply - 1);
     if alpha >= beta { return alpha }
 
-    // check extensions
-    depth += i8::from(in_check);
+    // a draw can be forced by repeating a position from earlier in the search
+    let draw_score: i16 = ctx.draw_score(pos);
+    if alpha < draw_score && pos.upcoming_repetition(ctx.ply) {
+        alpha = draw_score;
+        if alpha >= beta { return alpha }
+    }
+
+    // check extensions, already applied before a singular verification search
+    depth += i8::from(in_check && excluded == 0);
 
     // qsearch at depth 0
-    if depth <= 0 { return qsearch(pos, alpha, beta, &mut ctx.nodes) }
+    if depth <= 0 { return qsearch(pos, alpha, beta, 0, ctx) }
     ctx.nodes += 1;
 
-    // probing hash table
+    // probing hash table, skipped when verifying a singular move
     let mut hash_move: u16 = 0;
-    let mut write_to_hash: bool = true;
-    if let Some(res) = ctx.hash_table.probe(pos.state.zobrist, ctx.ply) {
-        write_to_hash = depth > res.depth;
-        hash_move = res.best_move;
+    let tt_entry: Option<HashEntry> = if excluded == 0 {ctx.hash_table.probe(pos.state.zobrist, ctx.ply)} else {None};
+    let tt_pv: bool = pv || tt_entry.is_some_and(|res| res.pv());
+    if let Some(res) = tt_entry {
+        // a different position with the same key can hand us anything
+        if pos.is_pseudo_legal(res.best_move) { hash_move = res.best_move }
         // hash score pruning
         if ctx.ply > 0 && pos.state.halfmove_clock <= 90 && res.depth >= depth &&
-            match res.bound {
+            match res.bound() {
                 Bound::EXACT => !pv, // want nice pv lines
                 Bound::LOWER => res.score >= beta,
                 Bound::UPPER => res.score <= alpha,
@@ -128,54 +195,159 @@ fn search(pos: &mut Position, nt: NodeType, mut alpha: i16, mut beta: i16, mut d
             } { return res.score }
     }
 
-    // pruning
-    if !pv && !in_check && beta.abs() < MATE_THRESHOLD {
-        let lazy_eval: i16 = pos.lazy_eval();
+    // exact mate scores from generated tables
+    if ctx.ply > 0 && excluded == 0 {
+        if let Some(score) = ctx.dtm.probe(pos, ctx.ply) {
+            ctx.tb_hits += 1;
+            return if score == 0 {ctx.draw_score(pos)} else {score}
+        }
+    }
+
+    // tablebase probing, only straight after a capture or pawn move
+    if ctx.ply > 0 && excluded == 0 && pos.state.halfmove_clock == 0 && ctx.tablebases.can_probe(pos) {
+        if let Some(wdl) = ctx.tablebases.probe_wdl(pos) {
+            ctx.tb_hits += 1;
+            let (score, bound): (i16, u8) = match wdl {
+                2 => (TB_WIN - ctx.ply, Bound::LOWER),
+                -2 => (-TB_WIN + ctx.ply, Bound::UPPER),
+                _ => (ctx.draw_score(pos), Bound::EXACT),
+            };
+            if bound == Bound::EXACT || (bound == Bound::LOWER && score >= beta) || (bound == Bound::UPPER && score <= alpha) {
+                ctx.hash_table.push(pos.state.zobrist, HashEntry::new(0, score, -MAX, min(depth + 6, MAX_PLY - 1), bound, tt_pv), ctx.ply);
+                return score
+            }
+        }
+    }
+
+    // static eval, and whether it has improved since our last move
+    let eval: i16 = match tt_entry {
+        _ if in_check => -MAX,
+        Some(res) if res.eval != -MAX => res.eval,
+        _ => ctx.eval(pos),
+    };
+    ctx.stack[ply].eval = eval;
+    ctx.stack[ply].in_check = in_check;
+    let improving: bool = !in_check && (ply < 2 || ctx.stack[ply - 2].in_check || eval > ctx.stack[ply - 2].eval);
 
+    // pruning
+    if !pv && !in_check && excluded == 0 && beta.abs() < MATE_THRESHOLD {
         // reverse futility pruning
-        let margin: i16 = lazy_eval - 120 * i16::from(depth);
+        let margin: i16 = eval - 120 * i16::from(depth - i8::from(improving));
         if depth <= 8 && margin >= beta { return margin }
 
+        // razoring
+        if depth <= 2 && eval + 250 * i16::from(depth) <= alpha {
+            let score: i16 = qsearch(pos, alpha, beta, 0, ctx);
+            if score <= alpha { return score }
+        }
+
         // null move pruning
-        if allow_null && depth >= 3 && pos.phase >= 6 && lazy_eval >= beta {
+        if allow_null && depth >= 3 && pos.phase >= 6 && eval >= beta {
+            ctx.stack[ply].m = 0;
+            ctx.stack[ply + 1].double_exts = ctx.stack[ply].double_exts;
+            ctx.ply += 1;
             let copy: (u16, u64) = pos.do_null();
-            let score: i16 = -search(pos, NodeType::encode(false, false, false), -beta, -beta + 1, depth - 3, ctx, &mut Vec::new());
+            let score: i16 = -search(pos, NodeType::encode(false, false, false, !cut_node), -beta, -beta + 1, depth - 3, ctx);
             pos.undo_null(copy);
+            ctx.ply -= 1;
             if score >= beta {return score}
         }
     }
 
-    // generate and score moves
-    let mut moves: MoveList = MoveList::default();
-    let mut scores: MoveList = MoveList::default();
-    pos.gen_moves::<ALL>(&mut moves);
-    pos.score_moves(&moves, &mut scores, hash_move, ctx.ply, &ctx.killer_table);
+    // singular extensions, verified by searching every other move at reduced depth
+    // against a bound just below the hash score, with a limited number of double extensions
+    let mut hash_move_ext: i8 = 0;
+    if let Some(res) = tt_entry {
+        if ply > 0 && depth >= SINGULAR_DEPTH && hash_move != 0 && res.bound() == Bound::LOWER
+            && res.depth >= depth - 3 && res.score.abs() < MATE_THRESHOLD {
+            let s_beta: i16 = res.score - 2 * i16::from(depth);
+            ctx.stack[ply].excluded = hash_move;
+            let score: i16 = search(pos, NodeType::encode(false, in_check, false, cut_node), s_beta - 1, s_beta, (depth - 1) / 2, ctx);
+            ctx.stack[ply].excluded = 0;
+            // the verification search shares this ply's pv line
+            ctx.pv_table.clear(ply);
+            if score < s_beta {
+                hash_move_ext = 1 + i8::from(!pv && score < s_beta - DOUBLE_EXT_MARGIN && ctx.stack[ply].double_exts < MAX_DOUBLE_EXTS);
+            } else if s_beta >= beta {
+                // multi-cut, more than one move fails high
+                return s_beta
+            } else if res.score >= beta {
+                hash_move_ext = -1;
+            }
+        }
+    }
 
     // is the threshold for late move reductions satisfied?
-    let can_lmr: bool = depth >= 2 && ctx.ply > 0 && !in_check;
+    let can_lmr: bool = depth >= 2 && ply > 0 && !in_check;
+    let can_prune: bool = !pv && !in_check;
+    let lmp_moves: u16 = if (depth as usize) < LMP_MOVES.len() {LMP_MOVES[depth as usize] * (2 + u16::from(improving)) / 3} else {u16::MAX};
+
+    // moves are generated lazily, in stages
+    let prevs: [Option<(usize, usize)>; 2] = ctx.prev_moves(ply);
+    let counter_move: u16 = prevs[0].map_or(0, |prev| ctx.counter_moves.get(usize::from(pos.c), prev));
+    let mut picker: MovePicker = MovePicker::new(hash_move, ctx.killer_table.0[ply], counter_move, prevs);
 
     ctx.ply += 1;
     let mut bound: u8 = Bound::UPPER;
     let mut best_move: u16 = 0;
     let mut best_score: i16 = -MAX;
     let mut legal_moves: u16 = 0;
-    while let Some((m, m_score)) = pick_move(&mut moves, &mut scores) {
+    let mut quiets: MoveList = MoveList::default();
+    while let Some((m, m_score)) = picker.next(pos, ctx) {
+        if m == excluded { continue }
+        let quiet: bool = m & 0b1100_0000_0000_0000 == 0;
+
+        // only prune once a move has been found that doesn't get mated, and never moves that give check
+        let prune: bool = can_prune && best_score > -MATE_THRESHOLD && (
+            // late move pruning
+            (quiet && legal_moves >= lmp_moves)
+            // futility pruning
+            || (quiet && depth <= 6 && eval + 80 + 80 * i16::from(depth) <= alpha)
+            // see pruning of moves that lose too much material
+            || (depth <= 6 && m_score < HASH_MOVE
+                && !pos.see(m, if quiet {-60 * i16::from(depth)} else {-20 * i16::from(depth) * i16::from(depth)}))
+        );
+
+        if prune && !pos.gives_check(m) { continue }
+
+        ctx.stack[ply].m = m;
+        ctx.stack[ply].piece = pos.piece_and_to(m).0;
         if pos.do_move(m) { continue }
         legal_moves += 1;
-
-        // late move reductions
         let gives_check: bool = pos.is_in_check();
-        let reduce: i8 = i8::from(can_lmr && !gives_check && legal_moves > 1 && m_score < 300);
+        if quiet { quiets.push(m) }
+
+        let ext: i8 = if m == hash_move {hash_move_ext} else {0};
+        let new_depth: i8 = depth - 1 + ext;
+        ctx.stack[ply + 1].double_exts = ctx.stack[ply].double_exts + u8::from(ext == 2);
+
+        // late move reductions of quiets
+        let reduce: i8 = if can_lmr && !gives_check && legal_moves > 1 && quiet {
+            let mut r: i8 = ctx.lmr_table[min(depth as usize, 63)][min(legal_moves as usize, 63)];
+            r -= i8::from(tt_pv);
+            r += i8::from(!improving);
+            r += i8::from(cut_node);
+            if m_score >= COUNTER_MOVE {
+                // killers and countermoves
+                r -= 1;
+            } else {
+                r -= ((i32::from(m_score) - i32::from(QUIET)) / HISTORY_MAX) as i8;
+            }
+            r.clamp(0, max(new_depth - 1, 0))
+        } else { 0 };
 
-        // pvs
-        let mut sub_pv: Vec<u16> = Vec::new();
+        // pvs, with a full depth zero window verification if a reduced move beats alpha
         let score: i16 = if legal_moves == 1 {
-            -search(pos, NodeType::encode(pv, gives_check, false), -beta, -alpha, depth - 1, ctx, &mut sub_pv)
+            -search(pos, NodeType::encode(pv, gives_check, false, !pv && !cut_node), -beta, -alpha, new_depth, ctx)
         } else {
-            let zw_score: i16 = -search(pos, NodeType::encode(false, gives_check, true), -alpha - 1, -alpha, depth - 1 - reduce, ctx, &mut sub_pv);
-            if (alpha != beta - 1 || reduce > 0) && zw_score > alpha {
-                -search(pos, NodeType::encode(pv, gives_check, false), -beta, -alpha, depth - 1, ctx, &mut sub_pv)
-            } else { zw_score }
+            let mut score: i16 = -search(pos, NodeType::encode(false, gives_check, true, true), -alpha - 1, -alpha, new_depth - reduce, ctx);
+            if reduce > 0 && score > alpha {
+                score = -search(pos, NodeType::encode(false, gives_check, true, !cut_node), -alpha - 1, -alpha, new_depth, ctx);
+            }
+            if pv && score > alpha {
+                score = -search(pos, NodeType::encode(true, gives_check, false, false), -beta, -alpha, new_depth, ctx);
+            }
+            score
         };
 
         pos.undo_move();
@@ -187,89 +359,221 @@ fn search(pos: &mut Position, nt: NodeType, mut alpha: i16, mut beta: i16, mut d
                 alpha = score;
                 bound = Bound::EXACT;
                 // update pv
-                pv_line.clear();
-                pv_line.push(m);
-                pv_line.append(&mut sub_pv);
+                ctx.pv_table.update(ply, m);
                 if score >= beta {
                     bound = Bound::LOWER;
-                    // push to killer move table if not a capture
-                    if m & 0b0100_0000_0000_0000 == 0 { ctx.killer_table.push(m, ctx.ply) };
+                    // push to killer move table and update histories if quiet
+                    if quiet {
+                        ctx.killer_table.push(m, ctx.ply);
+                        ctx.update_quiet_history(pos, ply, m, &quiets, depth);
+                    }
                     break
                 }
             }
         }
     }
     ctx.ply -= 1;
-    if legal_moves == 0 { return i16::from(in_check) * (-MAX + ctx.ply) }
-    if write_to_hash && !ctx.abort { ctx.hash_table.push(pos.state.zobrist, best_move, depth, bound, best_score, ctx.ply) }
+    if legal_moves == 0 { return if excluded != 0 {alpha} else if in_check {-MAX + ctx.ply} else {ctx.draw_score(pos)} }
+    if excluded == 0 && !ctx.abort { ctx.hash_table.push(pos.state.zobrist, HashEntry::new(best_move, best_score, eval, depth, bound, tt_pv), ctx.ply) }
 
     best_score
 }
 
 /// Quiescence search:
 /// - Fail-soft
+/// - Hash table cutoffs and move ordering, results stored at depth 0
 /// - Delta pruning
-fn qsearch(pos: &mut Position, mut alpha: i16, beta: i16, nodes: &mut u64) -> i16 {
-    *nodes += 1;
-    let mut stand_pat: i16 = pos.lazy_eval();
-
-    if stand_pat >= beta { return stand_pat }
-    if alpha < stand_pat { alpha = stand_pat }
+/// - Every evasion when in check, so that mates are found
+/// - Quiet checks at the first ply, if enabled
+fn qsearch(pos: &mut Position, mut alpha: i16, beta: i16, depth: i8, ctx: &mut SearchContext) -> i16 {
+    ctx.nodes += 1;
+    if ctx.ply >= i16::from(MAX_PLY) - 1 { return ctx.eval(pos) }
+    let in_check: bool = pos.is_in_check();
 
-    // generate and score moves
-    let mut captures: MoveList = MoveList::default();
-    let mut scores: MoveList = MoveList::default();
-    pos.gen_moves::<CAPTURES>(&mut captures);
-    pos.score_captures(&captures, &mut scores);
+    // probing hash table, only captures are useful as hash moves unless in check
+    let mut hash_move: u16 = 0;
+    let tt_entry: Option<HashEntry> = ctx.hash_table.probe(pos.state.zobrist, ctx.ply);
+    if let Some(res) = tt_entry {
+        if match res.bound() {
+            Bound::EXACT => true,
+            Bound::LOWER => res.score >= beta,
+            Bound::UPPER => res.score <= alpha,
+            _ => false
+        } { return res.score }
+        if (in_check || res.best_move & 0b0100_0000_0000_0000 > 0) && pos.is_pseudo_legal(res.best_move) { hash_move = res.best_move }
+    }
+    let tt_pv: bool = tt_entry.is_some_and(|res| res.pv());
+
+    let eval: i16 = match tt_entry {
+        _ if in_check => -MAX,
+        Some(res) if res.eval != -MAX => res.eval,
+        _ => ctx.eval(pos),
+    };
+    let mut best_score: i16 = if in_check {-MAX + ctx.ply} else {eval};
+    if best_score >= beta {
+        ctx.hash_table.push(pos.state.zobrist, HashEntry::new(0, best_score, eval, 0, Bound::LOWER, tt_pv), ctx.ply);
+        return best_score
+    }
+    let original_alpha: i16 = alpha;
+    alpha = max(alpha, best_score);
 
-    while let Some((m, m_score)) = pick_move(&mut captures, &mut scores) {
+    let mut best_move: u16 = 0;
+    let mut picker: MovePicker = if in_check {MovePicker::evasions(hash_move)} else {MovePicker::captures(hash_move, depth == 0 && ctx.qsearch_checks)};
+    while let Some((m, m_score)) = picker.next(pos, ctx) {
         // delta pruning
-        if stand_pat + m_score as i16 / 5 + 200 < alpha { break }
+        if !in_check && best_score + m_score as i16 / 5 + 200 < alpha { break }
 
         if pos.do_move(m) { continue }
-        let score: i16 = -qsearch(pos, -beta, -alpha, nodes);
+        ctx.ply += 1;
+        let score: i16 = -qsearch(pos, -beta, -alpha, depth - 1, ctx);
+        ctx.ply -= 1;
         pos.undo_move();
 
-        if score > stand_pat {
-            stand_pat = score;
+        if score > best_score {
+            best_score = score;
             if score > alpha {
                 alpha = score;
-                if score >= beta { return score }
+                best_move = m;
+                if score >= beta { break }
             }
         }
     }
-    stand_pat
+
+    let bound: u8 = if best_score >= beta {Bound::LOWER} else if best_score > original_alpha {Bound::EXACT} else {Bound::UPPER};
+    ctx.hash_table.push(pos.state.zobrist, HashEntry::new(best_move, best_score, eval, 0, bound, tt_pv), ctx.ply);
+    best_score
+}
+
+/// Prints search info for the given depth, where the score may only be a bound.
+fn report(pos: &Position, ctx: &SearchContext, depth: i8, score: i16, bound: &str, pv_line: &[u16]) {
+    if ctx.silent { return }
+    let t: u128 = ctx.time.elapsed().as_millis();
+    let (stype, sval): (&str, i16) = if score.abs() >= MATE_THRESHOLD {
+        ("mate", if score < 0 { score.abs() - MAX } else { MAX - score + 1 } / 2)
+    } else {
+        ("cp", score)
+    };
+    let nps: u32 = ((ctx.nodes as f64) * 1000.0 / (t as f64)) as u32;
+    let pv_str: String = pv_line.iter().map(|m| u16_to_uci(pos, *m)).collect::<String>();
+    println!("info depth {} score {} {}{} time {} nodes {} nps {} tbhits {} pv {}", depth, stype, sval, bound, t, ctx.nodes, nps, ctx.tb_hits, pv_str);
 }
 
 /// Root search function:
 /// - Iterative deepening
-/// - Handles uci output
-pub fn go(pos: &mut Position, allocated_depth: i8, ctx: &mut SearchContext) {
+/// - Handles uci output, unless silenced
+/// - Returns the best move and its score
+pub fn go(pos: &mut Position, allocated_depth: i8, ctx: &mut SearchContext) -> (u16, i16) {
     let mut best_move: u16 = 0;
+    let mut best_score: i16 = 0;
     ctx.reset();
+    ctx.root_side = pos.c;
+    ctx.root_prevs = [1, 2].map(|ago| pos.prev_move(ago));
+    ctx.hash_table.increment_age();
+
+    // play the dtz-optimal move straight away in tablebase positions
+    if ctx.tablebases.can_probe(pos) {
+        if let Some((m, score, probes)) = ctx.tablebases.probe_root(pos) {
+            ctx.tb_hits += probes;
+            report(pos, ctx, 1, score, "", &[m]);
+            if !ctx.silent { println!("bestmove {}", u16_to_uci(pos, m)) }
+            return (m, score)
+        }
+    }
 
     for d in 1..=allocated_depth {
+        // always finish the first iteration, so there is a move to play
+        ctx.can_abort = d > 1;
         let in_check: bool = pos.is_in_check();
-        let mut pv_line: Vec<u16> = Vec::new();
-        let score: i16 = search(pos, NodeType::encode(true, in_check, false), -MAX, MAX, d, ctx, &mut pv_line);
+        let mut pv_line: Vec<u16>;
+
+        // aspiration windows around the previous score, widened on failure,
+        // and searched at reduced depth after failing high
+        let mut delta: i16 = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta): (i16, i16) = if d >= ASPIRATION_DEPTH && best_score.abs() < MATE_THRESHOLD {
+            (max(best_score - delta, -MAX), min(best_score + delta, MAX))
+        } else { (-MAX, MAX) };
+        let mut search_depth: i8 = d;
+        let score: i16 = loop {
+            let score: i16 = search(pos, NodeType::encode(true, in_check, false, false), alpha, beta, search_depth, ctx);
+            pv_line = ctx.pv_table.line(0).to_vec();
+            if ctx.abort { break score }
+            if score <= alpha && alpha > -MAX {
+                report(pos, ctx, d, score, " upperbound", &pv_line);
+                beta = (alpha + beta) / 2;
+                alpha = max(score - delta, -MAX);
+                search_depth = d;
+            } else if score >= beta && beta < MAX {
+                report(pos, ctx, d, score, " lowerbound", &pv_line);
+                beta = min(score + delta, MAX);
+                search_depth = max(search_depth - 1, d - 3);
+            } else { break score }
+            delta += delta / 2;
+            if delta >= ASPIRATION_MAX { (alpha, beta) = (-MAX, MAX) }
+        };
 
         // end search if out of time
         let t: u128 = ctx.time.elapsed().as_millis();
-        if t >= ctx.alloc_time || ctx.abort { break }
+        if (t >= ctx.alloc_time && d > 1) || ctx.abort { break }
 
         best_move = pv_line[0];
-        let (stype, sval): (&str, i16) = if score.abs() >= MATE_THRESHOLD {
-            ("mate", if score < 0 { score.abs() - MAX } else { MAX - score + 1 } / 2)
-        } else {
-            ("cp", score)
-        };
-        let nps: u32 = ((ctx.nodes as f64) * 1000.0 / (t as f64)) as u32;
-        let pv_str: String = pv_line.iter().map(|m| u16_to_uci(pos, *m)).collect::<String>();
-        println!("info depth {} score {} {} time {} nodes {} nps {} pv {}", d, stype, sval, t, ctx.nodes, nps, pv_str);
+        best_score = score;
+        report(pos, ctx, d, score, "", &pv_line);
 
         // stop searching if mate found
         if score.abs() >= MATE_THRESHOLD { break }
     }
-    println!("bestmove {}", u16_to_uci(pos, best_move));
+    if !ctx.silent { println!("bestmove {}", u16_to_uci(pos, best_move)) }
     ctx.killer_table.clear();
+    (best_move, best_score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fen, uci_to_u16};
+
+    fn context(contempt: i16) -> SearchContext {
+        let mut ctx: SearchContext = SearchContext::new(HashTable::new(), KillerTable([[0; KILLERS_PER_PLY]; MAX_PLY as usize]));
+        ctx.hash_table.resize(1).unwrap();
+        ctx.alloc_time = u128::MAX;
+        ctx.silent = true;
+        ctx.contempt = contempt;
+        ctx
+    }
+
+    /// Draws are scored `contempt` below zero for the root side, whichever side the draw is reached with.
+    #[test]
+    fn draw_score_sign() {
+        for fen in [
+            // the fifty-move rule is reached with the opponent to move
+            "4k3/7r/8/8/8/8/R7/4K3 w - - 99 1",
+            "4k3/7r/8/8/8/8/R7/4K3 b - - 99 1",
+            // or after the opponent's reply
+            "4k3/7r/8/8/8/8/R7/4K3 w - - 98 1",
+            "4k3/7r/8/8/8/8/R7/4K3 b - - 98 1",
+        ] {
+            let mut pos: Position = parse_fen(fen);
+            let (_, score): (u16, i16) = go(&mut pos, 6, &mut context(50));
+            assert!((score + 50).abs() <= 1, "{fen}: {score}");
+            let mut ctx: SearchContext = context(50);
+            ctx.analyse_mode = true;
+            let (_, score): (u16, i16) = go(&mut pos, 6, &mut ctx);
+            assert!(score.abs() <= 1, "{fen}: {score}");
+            ctx.analysis_contempt = true;
+            let (_, score): (u16, i16) = go(&mut pos, 6, &mut ctx);
+            assert!((score + 50).abs() <= 1, "{fen}: {score}");
+        }
+    }
+
+    /// Deep cutoffs still reward the cutoff move and punish the rest, by at most the bonus cap.
+    #[test]
+    fn quiet_history_at_large_depths() {
+        let pos: Position = parse_fen(STARTPOS);
+        let mut quiets: MoveList = MoveList::default();
+        ["g1f3", "e2e4"].iter().for_each(|uci| quiets.push(uci_to_u16(&pos, uci)));
+        let mut ctx: SearchContext = context(0);
+        ctx.update_quiet_history(&pos, 0, quiets.list[1], &quiets, MAX_PLY - 1);
+        assert_eq!(ctx.history.get(0, quiets.list[0]), -HISTORY_BONUS_MAX);
+        assert_eq!(ctx.history.get(0, quiets.list[1]), HISTORY_BONUS_MAX);
+    }
 }
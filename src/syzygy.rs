@@ -0,0 +1,932 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+use super::{lsb, consts::*, position::Position, movegen::MoveList};
+
+const TB_PIECES: usize = 7;
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/// Per-table flags stored in the file header.
+struct TBFlag;
+impl TBFlag {
+    const STM: u8 = 1;
+    const MAPPED: u8 = 2;
+    const WIN_PLIES: u8 = 4;
+    const LOSS_PLIES: u8 = 8;
+    const WIDE: u8 = 16;
+    const SINGLE_VALUE: u8 = 128;
+}
+
+/// Extra information returned alongside a probe result.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProbeState {
+    Ok,
+    /// Best move is a winning capture or pawn move, so the stored DTZ can't be trusted.
+    ZeroingBestMove,
+    /// DTZ tables are one-sided and this side to move isn't stored.
+    ChangeStm,
+}
+
+/// Index encoding tables, shared by all tablebase files.
+struct Encoding {
+    binomial: [[u64; 64]; TB_PIECES],
+    lead_pawn_idx: [[u64; 64]; TB_PIECES],
+    lead_pawns_size: [[u64; 4]; TB_PIECES],
+    map_pawns: [usize; 64],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+}
+
+static ENC: Encoding = encoding_init();
+
+const fn off_a1h8(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+/// Initialises ENC.
+const fn encoding_init() -> Encoding {
+    let mut enc: Encoding = Encoding {
+        binomial: [[0; 64]; TB_PIECES],
+        lead_pawn_idx: [[0; 64]; TB_PIECES],
+        lead_pawns_size: [[0; 4]; TB_PIECES],
+        map_pawns: [0; 64],
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+    };
+
+    // squares below the a1-h8 diagonal to 0..27
+    let mut code: usize = 0;
+    let mut sq: usize = 0;
+    while sq < 64 {
+        if off_a1h8(sq) < 0 {
+            enc.map_b1h1h7[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+
+    // a1-d1-d4 triangle to 0..9, with the diagonal squares last
+    code = 0;
+    sq = 0;
+    while sq < 28 {
+        if off_a1h8(sq) < 0 && sq & 7 <= 3 {
+            enc.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+    sq = 0;
+    while sq < 28 {
+        if off_a1h8(sq) == 0 && sq & 7 <= 3 {
+            enc.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+
+    // the 462 legal king pairs with the first king in the a1-d1-d4 triangle
+    let mut both_on_diagonal: [(usize, usize); 64] = [(0, 0); 64];
+    let mut num_diagonal: usize = 0;
+    let mut kk: u64 = 0;
+    let mut idx: usize = 0;
+    while idx < 10 {
+        let mut s1: usize = 0;
+        while s1 < 28 {
+            if enc.map_a1d1d4[s1] == idx && (idx > 0 || s1 == 1) {
+                let mut s2: usize = 0;
+                while s2 < 64 {
+                    if (KING_ATTACKS[s1] | (1 << s1)) & (1 << s2) > 0 || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                    } else if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal[num_diagonal] = (idx, s2);
+                        num_diagonal += 1;
+                    } else {
+                        enc.map_kk[idx][s2] = kk;
+                        kk += 1;
+                    }
+                    s2 += 1;
+                }
+            }
+            s1 += 1;
+        }
+        idx += 1;
+    }
+    let mut i: usize = 0;
+    while i < num_diagonal {
+        enc.map_kk[both_on_diagonal[i].0][both_on_diagonal[i].1] = kk;
+        kk += 1;
+        i += 1;
+    }
+
+    // binomial coefficients via pascal's rule
+    enc.binomial[0][0] = 1;
+    let mut n: usize = 1;
+    while n < 64 {
+        let mut k: usize = 0;
+        while k < TB_PIECES && k <= n {
+            enc.binomial[k][n] = if k > 0 {enc.binomial[k - 1][n - 1]} else {0} + if k < n {enc.binomial[k][n - 1]} else {0};
+            k += 1;
+        }
+        n += 1;
+    }
+
+    // leading pawn group encoding, a2-h7 mapped to 47..0 from the edges inwards
+    let mut lead: usize = 1;
+    while lead < TB_PIECES - 1 {
+        let mut file: usize = 0;
+        while file < 4 {
+            let mut idx: u64 = 0;
+            let mut rank: usize = 1;
+            while rank < 7 {
+                let sq: usize = 8 * rank + file;
+                if lead == 1 {
+                    enc.map_pawns[sq] = 47 - 2 * (6 * file + rank - 1);
+                    enc.map_pawns[sq ^ 7] = 46 - 2 * (6 * file + rank - 1);
+                }
+                enc.lead_pawn_idx[lead][sq] = idx;
+                idx += enc.binomial[lead - 1][enc.map_pawns[sq]];
+                rank += 1;
+            }
+            enc.lead_pawns_size[lead][file] = idx;
+            file += 1;
+        }
+        lead += 1;
+    }
+    enc
+}
+
+/// Decompression data for one (side to move, leading file) pair of a table.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    num_blocks: usize,
+    block_size: usize,
+    span: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    map_idx: [u16; 4],
+}
+
+/// A single WDL or DTZ table, loaded into memory on first use.
+struct Table {
+    data: Vec<u8>,
+    dtz: bool,
+    map: usize,
+    symmetric: bool,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pawn_count: [usize; 2],
+    items: [[PairsData; 4]; 2],
+}
+
+impl Table {
+    fn load(path: &PathBuf, name: &str, dtz: bool) -> Option<Self> {
+        let data: Vec<u8> = fs::read(path).ok()?;
+        if data.len() < 5 || data[..4] != if dtz {DTZ_MAGIC} else {WDL_MAGIC} { return None }
+        let (white, black): (&str, &str) = name.split_once('v')?;
+        let count = |s: &str, ch: char| s.chars().filter(|&c| c == ch).count();
+        let (wp, bp): (usize, usize) = (count(white, 'P'), count(black, 'P'));
+        let lead_white: bool = bp == 0 || (wp > 0 && bp >= wp);
+        let mut table: Self = Self {
+            data,
+            dtz,
+            map: 0,
+            symmetric: white == black,
+            piece_count: white.len() + black.len(),
+            has_pawns: wp + bp > 0,
+            has_unique_pieces: "QRBNP".chars().any(|ch| count(white, ch) == 1 || count(black, ch) == 1),
+            pawn_count: if lead_white {[wp, bp]} else {[bp, wp]},
+            items: Default::default(),
+        };
+        table.init()?;
+        Some(table)
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        &self.items[if self.dtz {0} else {stm}][if self.has_pawns {file} else {0}]
+    }
+
+    fn u16_le(&self, at: usize) -> u16 { u16::from_le_bytes([self.data[at], self.data[at + 1]]) }
+    fn u32_le(&self, at: usize) -> u32 { u32::from_le_bytes(self.data[at..at + 4].try_into().unwrap()) }
+    fn u32_be(&self, at: usize) -> u32 { u32::from_be_bytes(self.data[at..at + 4].try_into().unwrap()) }
+    fn u64_be(&self, at: usize) -> u64 { u64::from_be_bytes(self.data[at..at + 8].try_into().unwrap()) }
+
+    /// Parses the table header, returning None if the file is truncated.
+    fn init(&mut self) -> Option<()> {
+        let sides: usize = if !self.dtz && !self.symmetric {2} else {1};
+        let max_file: usize = if self.has_pawns {3} else {0};
+        let pp: bool = self.has_pawns && self.pawn_count[1] > 0;
+        let mut p: usize = 5;
+        for f in 0..=max_file {
+            let (o1, o2): (u8, u8) = (*self.data.get(p)?, if pp {*self.data.get(p + 1)?} else {0xFF});
+            let order: [[u8; 2]; 2] = [[o1 & 0xF, if pp {o2 & 0xF} else {0xF}], [o1 >> 4, if pp {o2 >> 4} else {0xF}]];
+            p += 1 + usize::from(pp);
+            for k in 0..self.piece_count {
+                let byte: u8 = *self.data.get(p)?;
+                for (i, side) in self.items.iter_mut().enumerate().take(sides) {
+                    side[f].pieces[k] = if i == 1 {byte >> 4} else {byte & 0xF};
+                }
+                p += 1;
+            }
+            for (i, o) in order.iter().enumerate().take(sides) {
+                self.set_groups(i, f, *o);
+            }
+        }
+        p += p & 1;
+        for f in 0..=max_file {
+            for i in 0..sides { p = self.set_sizes(i, f, p)? }
+        }
+        if self.dtz { p = self.set_dtz_map(p, max_file)? }
+        for f in 0..=max_file {
+            for i in 0..sides {
+                self.items[i][f].sparse_index = p;
+                p += self.items[i][f].sparse_index_size * 6;
+            }
+        }
+        for f in 0..=max_file {
+            for i in 0..sides {
+                self.items[i][f].block_length = p;
+                p += self.items[i][f].block_length_size * 2;
+            }
+        }
+        for f in 0..=max_file {
+            for i in 0..sides {
+                p = (p + 0x3F) & !0x3F;
+                self.items[i][f].data = p;
+                p += self.items[i][f].num_blocks * self.items[i][f].block_size;
+            }
+        }
+        (p <= self.data.len()).then_some(())
+    }
+
+    /// Splits the pieces into groups and computes the starting index of each group.
+    fn set_groups(&mut self, side: usize, file: usize, order: [u8; 2]) {
+        let (has_pawns, unique, count): (bool, bool, usize) = (self.has_pawns, self.has_unique_pieces, self.piece_count);
+        let pp: bool = has_pawns && self.pawn_count[1] > 0;
+        let d: &mut PairsData = &mut self.items[side][file];
+        let mut n: usize = 0;
+        let mut first_len: i32 = if has_pawns {0} else if unique {3} else {2};
+        d.group_len[0] = 1;
+        for i in 1..count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let mut next: usize = if pp {2} else {1};
+        let mut free_squares: usize = 64 - d.group_len[0] - if pp {d.group_len[1]} else {0};
+        let mut idx: u64 = 1;
+        let mut k: u8 = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if has_pawns {ENC.lead_pawns_size[d.group_len[0]][file]} else if unique {31332} else {462};
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= ENC.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= ENC.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    /// Reads the huffman code and block layout.
+    fn set_sizes(&mut self, side: usize, file: usize, mut p: usize) -> Option<usize> {
+        let header: [u8; 10] = self.data.get(p..p + 10)?.try_into().unwrap();
+        let flags: u8 = header[0];
+        self.items[side][file].flags = flags;
+        if flags & TBFlag::SINGLE_VALUE > 0 {
+            self.items[side][file].min_sym_len = header[1];
+            return Some(p + 2);
+        }
+        let (max_sym_len, min_sym_len): (u8, u8) = (header[8], header[9]);
+        let lowest_sym: usize = p + 10;
+        let len: usize = max_sym_len.checked_sub(min_sym_len)? as usize + 1;
+        p += 10 + 2 * len;
+        let num_syms: usize = *self.data.get(p)? as usize | (*self.data.get(p + 1)? as usize) << 8;
+        let btree: usize = p + 2;
+        if btree + 3 * num_syms > self.data.len() { return None }
+
+        // canonical huffman: longer symbols have lower values, so base64 is decreasing
+        let mut base64: Vec<u64> = vec![0; len];
+        for i in (0..len - 1).rev() {
+            let (lo, hi): (u64, u64) = (self.u16_le(lowest_sym + 2 * i) as u64, self.u16_le(lowest_sym + 2 * i + 2) as u64);
+            base64[i] = base64[i + 1].wrapping_add(lo).wrapping_sub(hi) / 2;
+        }
+        for (i, b) in base64.iter_mut().enumerate() {
+            *b = b.checked_shl(64 - i as u32 - min_sym_len as u32).unwrap_or(0);
+        }
+
+        let d: &mut PairsData = &mut self.items[side][file];
+        let end: usize = d.group_len.iter().position(|&l| l == 0).unwrap_or(TB_PIECES);
+        d.block_size = 1 << header[1];
+        d.span = 1 << header[2];
+        d.sparse_index_size = d.group_idx[end].div_ceil(d.span as u64) as usize;
+        d.num_blocks = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        d.block_length_size = d.num_blocks + header[3] as usize;
+        d.min_sym_len = min_sym_len;
+        d.lowest_sym = lowest_sym;
+        d.base64 = base64;
+        d.btree = btree;
+        d.symlen = vec![0; num_syms];
+        let mut visited: Vec<bool> = vec![false; num_syms];
+        for sym in 0..num_syms {
+            if !visited[sym] { self.set_symlen(side, file, sym, &mut visited) }
+        }
+        Some(btree + 3 * num_syms + (num_syms & 1))
+    }
+
+    fn left_right(&self, btree: usize, sym: usize) -> (usize, usize) {
+        let lr: &[u8] = &self.data[btree + 3 * sym..btree + 3 * sym + 3];
+        (((lr[1] as usize & 0xF) << 8) | lr[0] as usize, ((lr[2] as usize) << 4) | (lr[1] as usize >> 4))
+    }
+
+    /// Number of values (minus one) represented by each symbol, computed recursively.
+    fn set_symlen(&mut self, side: usize, file: usize, sym: usize, visited: &mut Vec<bool>) {
+        visited[sym] = true;
+        let (left, right): (usize, usize) = self.left_right(self.items[side][file].btree, sym);
+        if right == 0xFFF { return }
+        if !visited[left] { self.set_symlen(side, file, left, visited) }
+        if !visited[right] { self.set_symlen(side, file, right, visited) }
+        let d: &mut PairsData = &mut self.items[side][file];
+        d.symlen[sym] = d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1);
+    }
+
+    fn set_dtz_map(&mut self, mut p: usize, max_file: usize) -> Option<usize> {
+        self.map = p;
+        for f in 0..=max_file {
+            let flags: u8 = self.items[0][f].flags;
+            if flags & TBFlag::MAPPED == 0 { continue }
+            if flags & TBFlag::WIDE > 0 {
+                p += p & 1;
+                for i in 0..4 {
+                    self.items[0][f].map_idx[i] = ((p - self.map) / 2 + 1) as u16;
+                    p += 2 * *self.data.get(p)? as usize + 2 * ((*self.data.get(p + 1)? as usize) << 8) + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    self.items[0][f].map_idx[i] = (p - self.map + 1) as u16;
+                    p += *self.data.get(p)? as usize + 1;
+                }
+            }
+        }
+        Some(p + (p & 1))
+    }
+
+    /// Finds the value stored at index `idx`.
+    fn decompress_pairs(&self, d: &PairsData, idx: u64) -> usize {
+        if d.flags & TBFlag::SINGLE_VALUE > 0 { return d.min_sym_len as usize }
+
+        // sparse index entries point to known offsets into the block lengths
+        let k: usize = (idx / d.span as u64) as usize;
+        let mut block: usize = self.u32_le(d.sparse_index + 6 * k) as usize;
+        let mut offset: i64 = self.u16_le(d.sparse_index + 6 * k + 4) as i64;
+        offset += (idx % d.span as u64) as i64 - (d.span / 2) as i64;
+        let block_len = |b: usize| self.u16_le(d.block_length + 2 * b) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_len(block) + 1;
+        }
+        while offset > block_len(block) {
+            offset -= block_len(block) + 1;
+            block += 1;
+        }
+
+        // walk the canonical huffman symbols in the block
+        let mut ptr: usize = d.data + block * d.block_size;
+        let mut buf64: u64 = self.u64_be(ptr);
+        ptr += 8;
+        let mut buf64_size: i32 = 64;
+        let min_sym_len: usize = d.min_sym_len as usize;
+        let mut sym: usize;
+        loop {
+            let mut len: usize = 0;
+            while buf64 < d.base64[len] { len += 1 }
+            sym = ((buf64 - d.base64[len]) >> (64 - len - min_sym_len)) as usize;
+            sym += self.u16_le(d.lowest_sym + 2 * len) as usize;
+            if offset < d.symlen[sym] as i64 + 1 { break }
+            offset -= d.symlen[sym] as i64 + 1;
+            len += min_sym_len;
+            buf64 <<= len;
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= (self.u32_be(ptr) as u64) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // expand the recursive pairing until a leaf
+        while d.symlen[sym] > 0 {
+            let (left, right): (usize, usize) = self.left_right(d.btree, sym);
+            if offset < d.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= d.symlen[left] as i64 + 1;
+                sym = right;
+            }
+        }
+        self.left_right(d.btree, sym).0
+    }
+
+    /// Encodes the position as an index into the table and looks up its value.
+    fn probe(&self, pos: &Position, black_stronger: bool, wdl: i32) -> (i32, ProbeState) {
+        let flip: bool = (self.symmetric && pos.c) || black_stronger;
+        let (flip_colour, flip_sq): (u8, usize) = if flip {(8, 56)} else {(0, 0)};
+        let stm: usize = usize::from(flip) ^ usize::from(pos.c);
+        let mut squares: [usize; TB_PIECES] = [0; TB_PIECES];
+        let mut pieces: [u8; TB_PIECES] = [0; TB_PIECES];
+        let mut size: usize = 0;
+        let mut lead_pawns: u64 = 0;
+        let mut tb_file: usize = 0;
+
+        // the leading pawn is the one closest to the edge, then lowest rank
+        if self.has_pawns {
+            let colour: usize = usize::from((self.get(0, 0).pieces[0] ^ flip_colour) >> 3 > 0);
+            lead_pawns = pos.pieces[PAWN] & pos.sides[colour];
+            let mut b: u64 = lead_pawns;
+            while b > 0 {
+                squares[size] = lsb!(b) as usize ^ flip_sq;
+                size += 1;
+                b &= b - 1;
+            }
+            let lead: usize = (0..size).max_by_key(|&i| ENC.map_pawns[squares[i]]).unwrap_or(0);
+            squares.swap(0, lead);
+            tb_file = std::cmp::min(squares[0] & 7, 7 - (squares[0] & 7));
+        }
+        let lead_pawns_cnt: usize = size;
+
+        if self.dtz {
+            let flags: u8 = self.get(stm, tb_file).flags;
+            if (flags & TBFlag::STM) as usize != stm && (!self.symmetric || self.has_pawns) {
+                return (0, ProbeState::ChangeStm)
+            }
+        }
+
+        let mut b: u64 = (pos.sides[0] | pos.sides[1]) ^ lead_pawns;
+        while b > 0 {
+            let sq: usize = lsb!(b) as usize;
+            let colour: u8 = u8::from(pos.sides[BLACK] & (1 << sq) > 0);
+            squares[size] = sq ^ flip_sq;
+            pieces[size] = (pos.squares[sq] + 1) | ((colour << 3) ^ flip_colour);
+            size += 1;
+            b &= b - 1;
+        }
+
+        // reorder pieces to match the sequence stored in the table
+        let d: &PairsData = self.get(stm, tb_file);
+        for i in lead_pawns_cnt..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break
+                }
+            }
+        }
+
+        // map the leading piece to the a1-d1-d4 triangle
+        if squares[0] & 7 > 3 {
+            squares.iter_mut().take(size).for_each(|sq| *sq ^= 7);
+        }
+
+        let mut idx: u64;
+        if self.has_pawns {
+            idx = ENC.lead_pawn_idx[lead_pawns_cnt][squares[0]];
+            squares[1..lead_pawns_cnt].sort_by_key(|&sq| ENC.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawns_cnt).skip(1) {
+                idx += ENC.binomial[i][ENC.map_pawns[sq]];
+            }
+        } else {
+            if squares[0] >> 3 > 3 {
+                squares.iter_mut().take(size).for_each(|sq| *sq ^= 56);
+            }
+            for i in 0..d.group_len[0] {
+                let off: i32 = off_a1h8(squares[i]);
+                if off == 0 { continue }
+                if off > 0 {
+                    squares.iter_mut().take(size).skip(i).for_each(|sq| *sq = ((*sq >> 3) | (*sq << 3)) & 63);
+                }
+                break
+            }
+            let (s0, s1, s2): (usize, usize, usize) = (squares[0], squares[1], squares[2]);
+            if self.has_unique_pieces {
+                let adjust1: usize = usize::from(s1 > s0);
+                let adjust2: usize = usize::from(s2 > s0) + usize::from(s2 > s1);
+                idx = if off_a1h8(s0) != 0 {
+                    ((ENC.map_a1d1d4[s0] * 63 + s1 - adjust1) * 62 + s2 - adjust2) as u64
+                } else if off_a1h8(s1) != 0 {
+                    ((6 * 63 + (s0 >> 3) * 28 + ENC.map_b1h1h7[s1]) * 62 + s2 - adjust2) as u64
+                } else if off_a1h8(s2) != 0 {
+                    (6 * 63 * 62 + 4 * 28 * 62 + (s0 >> 3) * 7 * 28 + ((s1 >> 3) - adjust1) * 28 + ENC.map_b1h1h7[s2]) as u64
+                } else {
+                    (6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + (s0 >> 3) * 7 * 6 + ((s1 >> 3) - adjust1) * 6 + (s2 >> 3) - adjust2) as u64
+                };
+            } else {
+                idx = ENC.map_kk[ENC.map_a1d1d4[s0]][s1];
+            }
+        }
+
+        // encode the remaining groups, each sorted by square
+        idx *= d.group_idx[0];
+        let mut start: usize = d.group_len[0];
+        let mut remaining_pawns: bool = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next: usize = 1;
+        while d.group_len[next] > 0 {
+            let len: usize = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n: u64 = 0;
+            for i in 0..len {
+                let sq: usize = squares[start + i];
+                let adjust: usize = squares[..start].iter().filter(|&&s| sq > s).count();
+                n += ENC.binomial[i + 1][sq - adjust - 8 * usize::from(remaining_pawns)];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value: usize = self.decompress_pairs(d, idx);
+        if !self.dtz { return (value as i32 - 2, ProbeState::Ok) }
+
+        // dtz values may be remapped and stored in moves rather than plies
+        let d: &PairsData = self.get(0, tb_file);
+        let mut value: usize = value;
+        if d.flags & TBFlag::MAPPED > 0 {
+            let base: usize = d.map_idx[[1, 3, 0, 2, 0][(wdl + 2) as usize]] as usize;
+            value = if d.flags & TBFlag::WIDE > 0 {
+                self.u16_le(self.map + 2 * (base + value)) as usize
+            } else {
+                self.data[self.map + base + value] as usize
+            };
+        }
+        let mut dtz: i32 = value as i32;
+        if (wdl == 2 && d.flags & TBFlag::WIN_PLIES == 0) || (wdl == -2 && d.flags & TBFlag::LOSS_PLIES == 0) || wdl.abs() == 1 {
+            dtz *= 2;
+        }
+        (dtz + 1, ProbeState::Ok)
+    }
+}
+
+/// DTZ of a position whose best move resets the fifty-move counter.
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+/// Material of one side in table naming order, e.g. "KRP".
+pub fn material(pos: &Position, side: usize) -> String {
+    let mut s: String = String::new();
+    for (pc, ch) in [(KING, 'K'), (QUEEN, 'Q'), (ROOK, 'R'), (BISHOP, 'B'), (KNIGHT, 'N'), (PAWN, 'P')] {
+        (0..(pos.pieces[pc] & pos.sides[side]).count_ones()).for_each(|_| s.push(ch));
+    }
+    s
+}
+
+/// Is this a table name like "KRPvKR", with both kings and at least one other piece?
+fn valid_signature(stem: &str) -> bool {
+    let Some((white, black)) = stem.split_once('v') else { return false };
+    let side = |pcs: &str| pcs.starts_with('K') && pcs[1..].chars().all(|ch| "QRBNP".contains(ch));
+    side(white) && side(black) && stem.len() > 3
+}
+
+/// Syzygy endgame tablebases:
+/// - WDL tables probed inside the search
+/// - DTZ tables probed at the root to pick moves that keep winning under the fifty-move rule
+#[derive(Default)]
+pub struct Tablebases {
+    files: HashMap<String, PathBuf>,
+    wdl: HashMap<String, Option<Table>>,
+    dtz: HashMap<String, Option<Table>>,
+    pub cardinality: u32,
+}
+
+impl Tablebases {
+    /// Scans the given directories (separated as in `PATH`) for tablebase files.
+    pub fn new(paths: &str) -> Self {
+        let mut tbs: Self = Self::default();
+        if paths.is_empty() || paths == "<empty>" { return tbs }
+        for dir in std::env::split_paths(paths) {
+            let Ok(entries) = fs::read_dir(&dir) else { continue };
+            for entry in entries.flatten() {
+                let name: String = entry.file_name().to_string_lossy().into_owned();
+                let Some((stem, ext)) = name.rsplit_once('.') else { continue };
+                if !matches!(ext, "rtbw" | "rtbz") || !valid_signature(stem) { continue }
+                if ext == "rtbw" { tbs.cardinality = std::cmp::max(tbs.cardinality, stem.len() as u32 - 1) }
+                tbs.files.insert(name, entry.path());
+            }
+        }
+        let found: usize = tbs.files.keys().filter(|name| name.ends_with(".rtbw")).count();
+        println!("info string found {found} tablebases, largest has {} pieces", tbs.cardinality);
+        tbs
+    }
+
+    /// Can the position be probed at all?
+    pub fn can_probe(&self, pos: &Position) -> bool {
+        pos.state.castle_rights == 0 && (pos.sides[0] | pos.sides[1]).count_ones() <= self.cardinality
+    }
+
+    fn probe_table(&mut self, pos: &Position, dtz: bool, wdl: i32) -> Option<(i32, ProbeState)> {
+        if (pos.sides[0] | pos.sides[1]).count_ones() == 2 { return Some((0, ProbeState::Ok)) }
+        let (white, black): (String, String) = (material(pos, WHITE), material(pos, BLACK));
+        let ext: &str = if dtz {"rtbz"} else {"rtbw"};
+        let mut name: String = format!("{white}v{black}");
+        let black_stronger: bool = !self.files.contains_key(&format!("{name}.{ext}"));
+        if black_stronger { name = format!("{black}v{white}") }
+        let path: &PathBuf = self.files.get(&format!("{name}.{ext}"))?;
+        let cache: &mut HashMap<String, Option<Table>> = if dtz {&mut self.dtz} else {&mut self.wdl};
+        let table: &Table = cache.entry(name).or_insert_with_key(|name| Table::load(path, name, dtz)).as_ref()?;
+        Some(table.probe(pos, black_stronger, wdl))
+    }
+
+    /// Tables may store "don't care" values where a capture (or, for DTZ, a pawn move)
+    /// is best, so those moves are searched before trusting the table.
+    fn search(&mut self, pos: &mut Position, check_zeroing: bool) -> Option<(i32, ProbeState)> {
+        let mut moves: MoveList = MoveList::default();
+        pos.gen_moves::<ALL>(&mut moves);
+        let (mut best, mut total, mut count): (i32, usize, usize) = (-2, 0, 0);
+        for i in 0..moves.len {
+            let m: u16 = moves.list[i];
+            let zeroing: bool = m & 0b0100_0000_0000_0000 > 0 || (check_zeroing && pos.squares[((m >> 6) & 63) as usize] as usize == PAWN);
+            if pos.do_move(m) { continue }
+            total += 1;
+            if !zeroing {
+                pos.undo_move();
+                continue
+            }
+            count += 1;
+            let res: Option<(i32, ProbeState)> = self.search(pos, false);
+            pos.undo_move();
+            let value: i32 = -res?.0;
+            if value > best {
+                best = value;
+                if value >= 2 { return Some((value, ProbeState::ZeroingBestMove)) }
+            }
+        }
+        let no_more_moves: bool = count > 0 && count == total;
+        let value: i32 = if no_more_moves {best} else {self.probe_table(pos, false, 0)?.0};
+        if best >= value {
+            return Some((best, if best > 0 || no_more_moves {ProbeState::ZeroingBestMove} else {ProbeState::Ok}))
+        }
+        Some((value, ProbeState::Ok))
+    }
+
+    /// Win/draw/loss for the side to move: 2 win, 1 cursed win, 0 draw, -1 blessed loss, -2 loss.
+    pub fn probe_wdl(&mut self, pos: &mut Position) -> Option<i32> {
+        self.search(pos, false).map(|res| res.0)
+    }
+
+    /// Distance to a zeroing move in plies, signed by the result for the side to move.
+    pub fn probe_dtz(&mut self, pos: &mut Position) -> Option<i32> {
+        let (wdl, state): (i32, ProbeState) = self.search(pos, true)?;
+        if wdl == 0 { return Some(0) }
+        if state == ProbeState::ZeroingBestMove { return Some(dtz_before_zeroing(wdl)) }
+        let (dtz, state): (i32, ProbeState) = self.probe_table(pos, true, wdl)?;
+        if state != ProbeState::ChangeStm {
+            return Some((dtz + 100 * i32::from(wdl.abs() == 1)) * wdl.signum())
+        }
+
+        // the table stores the other side to move, so do a 1-ply search
+        let mut min_dtz: i32 = 0xFFFF;
+        let mut moves: MoveList = MoveList::default();
+        pos.gen_moves::<ALL>(&mut moves);
+        for i in 0..moves.len {
+            let m: u16 = moves.list[i];
+            let zeroing: bool = m & 0b0100_0000_0000_0000 > 0 || pos.squares[((m >> 6) & 63) as usize] as usize == PAWN;
+            if pos.do_move(m) { continue }
+            let res: Option<i32> = if zeroing {
+                self.search(pos, false).map(|res| -dtz_before_zeroing(res.0))
+            } else {
+                self.probe_dtz(pos).map(|dtz| -dtz)
+            };
+            let Some(mut dtz) = res else {
+                pos.undo_move();
+                return None
+            };
+            if dtz == 1 && pos.is_in_check() && !pos.has_legal_moves() { min_dtz = 1 }
+            if !zeroing { dtz += dtz.signum() }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() { min_dtz = dtz }
+            pos.undo_move();
+        }
+        Some(if min_dtz == 0xFFFF {-1} else {min_dtz})
+    }
+
+    /// Ranks every root move by DTZ and returns the best one with its score and the number of moves probed.
+    /// Wins that can be converted before the fifty-move rule are preferred,
+    /// then the fastest zeroing; losing moves delay the loss as long as possible.
+    pub fn probe_root(&mut self, pos: &mut Position) -> Option<(u16, i16, u64)> {
+        let cnt50: i32 = pos.state.halfmove_clock as i32;
+        let mut moves: MoveList = MoveList::default();
+        pos.gen_moves::<ALL>(&mut moves);
+        let mut best: Option<(u16, i32, i32)> = None;
+        let mut probes: u64 = 0;
+        for i in 0..moves.len {
+            let m: u16 = moves.list[i];
+            if pos.do_move(m) { continue }
+            let res: Option<i32> = if pos.state.halfmove_clock == 0 {
+                self.probe_wdl(pos).map(|wdl| dtz_before_zeroing(-wdl))
+            } else {
+                self.probe_dtz(pos).map(|dtz| -dtz + (-dtz).signum())
+            };
+            let Some(mut dtz) = res else {
+                pos.undo_move();
+                return None
+            };
+            probes += 1;
+            if dtz == 2 && pos.is_in_check() && !pos.has_legal_moves() { dtz = 1 }
+            pos.undo_move();
+            let rank: i32 = match dtz {
+                1.. => if dtz + cnt50 <= 99 {1000} else {1000 - (dtz + cnt50)},
+                ..=-1 => if -dtz * 2 + cnt50 < 100 {-1000} else {-1000 + (-dtz + cnt50)},
+                0 => 0,
+            };
+            if best.is_none_or(|(_, r, d)| (rank, -dtz) > (r, -d)) { best = Some((m, rank, dtz)) }
+        }
+        let (m, rank, _) = best?;
+        let score: i16 = if rank >= 900 {TB_WIN} else if rank <= -900 {-TB_WIN} else {0};
+        Some((m, score, probes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_fen, to_fen, uci_to_u16, datagen::{Rng, legal_moves}};
+
+    fn tablebases() -> Tablebases {
+        Tablebases::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/syzygy"))
+    }
+
+    /// Random legal position with the given white and black pieces, e.g. "KRP" and "K".
+    fn random_fen(rng: &mut Rng, white: &str, black: &str) -> String {
+        loop {
+            let mut board: [char; 64] = ['1'; 64];
+            let placed: bool = white.chars().chain(black.chars().map(|ch| ch.to_ascii_lowercase())).all(|ch| {
+                let sq: usize = rng.next() as usize % 64;
+                let ok: bool = board[sq] == '1' && (!ch.eq_ignore_ascii_case(&'p') || (8..56).contains(&sq));
+                if ok { board[sq] = ch }
+                ok
+            });
+            if !placed { continue }
+            let ranks: Vec<String> = (0..8).rev().map(|r| board[8 * r..8 * r + 8].iter().collect()).collect();
+            let fen: String = format!("{} {} - - 0 1", ranks.join("/"), if rng.next() & 1 == 0 {'w'} else {'b'});
+            let mut pos: Position = parse_fen(&fen);
+            pos.c = !pos.c;
+            if !pos.is_in_check() { return to_fen(&parse_fen(&fen)) }
+        }
+    }
+
+    /// Same position with the colours swapped, or reflected between the a- and h-files.
+    fn flip(fen: &str) -> String {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let board: String = parts[0].split('/').rev().collect::<Vec<_>>().join("/");
+        let board: String = board.chars().map(|ch| if ch.is_ascii_uppercase() {ch.to_ascii_lowercase()} else {ch.to_ascii_uppercase()}).collect();
+        format!("{board} {} - - 0 1", if parts[1] == "w" {'b'} else {'w'})
+    }
+
+    fn mirror(fen: &str) -> String {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let board: String = parts[0].split('/').map(|rank| rank.chars().rev().collect::<String>()).collect::<Vec<_>>().join("/");
+        format!("{board} {} - - 0 1", parts[1])
+    }
+
+    fn probe(tbs: &mut Tablebases, fen: &str) -> (Option<i32>, Option<i32>) {
+        let mut pos: Position = parse_fen(fen);
+        (tbs.probe_wdl(&mut pos), tbs.probe_dtz(&mut pos))
+    }
+
+    #[test]
+    fn known_positions() {
+        let mut tbs: Tablebases = tablebases();
+        for (fen, wdl, dtz) in [
+            // mates in one, and the side to move losing its queen or stalemated
+            ("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", 2, 1),
+            ("k7/8/1K6/8/8/8/8/7R w - - 0 1", 2, 1),
+            ("k7/1Q6/8/8/8/8/8/7K b - - 0 1", 0, 0),
+            ("k7/8/1Q6/8/8/8/8/7K b - - 0 1", 0, 0),
+            // black to move, mated in 28 plies
+            ("8/8/8/8/4k3/8/8/R3K3 b - - 0 1", -2, -28),
+            // rook pawn against a king in the corner, and a centre pawn with the king in front
+            ("k7/8/8/8/8/8/P7/7K w - - 0 1", 0, 0),
+            ("k7/8/8/8/8/8/P7/7K b - - 0 1", 0, 0),
+            ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", 2, 3),
+            ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", -2, -4),
+            // rook against pawn, winning when the pawn is blockaded and drawn when it is protected
+            ("8/8/8/8/8/1k6/p7/K6R w - - 0 1", 2, 3),
+            ("8/8/4k3/8/8/8/3p4/3K3R b - - 0 1", -2, -2),
+            ("7R/8/8/8/8/k7/p7/4K3 w - - 0 1", 0, 0),
+        ] {
+            for fen in [fen.to_string(), flip(fen), mirror(fen), mirror(&flip(fen))] {
+                assert_eq!(probe(&mut tbs, &fen), (Some(wdl), Some(dtz)), "{fen}");
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_positions_agree() {
+        let mut tbs: Tablebases = tablebases();
+        let mut rng: Rng = Rng(0xD1B5_4A32_D192_ED03);
+        for (white, black) in [("KQ", "K"), ("KR", "K"), ("KP", "K"), ("KR", "KP")] {
+            for _ in 0..500 {
+                let fen: String = random_fen(&mut rng, white, black);
+                let expected: (Option<i32>, Option<i32>) = probe(&mut tbs, &fen);
+                if white.len() + black.len() == 3 { assert!(expected.0.is_some() && expected.1.is_some(), "{fen}") }
+                for other in [flip(&fen), mirror(&fen), mirror(&flip(&fen))] {
+                    assert_eq!(probe(&mut tbs, &other), expected, "{fen} and {other}");
+                }
+            }
+        }
+    }
+
+    /// The DTZ of a position follows from its children: one ply further from the best
+    /// non-zeroing move, or one ply for a winning zeroing move or mate.
+    #[test]
+    fn dtz_follows_from_children() {
+        let mut tbs: Tablebases = tablebases();
+        let mut rng: Rng = Rng(0x94D0_49BB_1331_11EB);
+        for (white, black) in [("KQ", "K"), ("KR", "K"), ("KP", "K"), ("KR", "KP")] {
+            for _ in 0..300 {
+                let mut pos: Position = parse_fen(&random_fen(&mut rng, white, black));
+                let Some(dtz) = tbs.probe_dtz(&mut pos) else { continue };
+                let moves: Vec<u16> = legal_moves(&mut pos);
+                if dtz == 0 || moves.is_empty() { continue }
+                let mut children: Vec<i32> = Vec::new();
+                for &m in &moves {
+                    let zeroing: bool = m & 0b0100_0000_0000_0000 > 0 || pos.squares[((m >> 6) & 63) as usize] as usize == PAWN;
+                    pos.do_move(m);
+                    let child: Option<i32> = if zeroing {tbs.probe_wdl(&mut pos).map(|wdl| -dtz_before_zeroing(wdl))} else {tbs.probe_dtz(&mut pos).map(|dtz| -dtz)};
+                    let mated: bool = pos.is_in_check() && legal_moves(&mut pos).is_empty();
+                    pos.undo_move();
+                    let Some(child) = child else { children.clear(); break };
+                    children.push(if mated {1} else if zeroing || child == 0 {child} else {child + child.signum()});
+                }
+                if children.is_empty() { continue }
+                let expected: i32 = if dtz > 0 {*children.iter().filter(|&&c| c > 0).min().unwrap()} else {*children.iter().min().unwrap()};
+                assert_eq!(dtz, expected, "{}", to_fen(&pos));
+            }
+        }
+    }
+
+    #[test]
+    fn root_move_choice() {
+        let mut tbs: Tablebases = tablebases();
+        for (fen, best, score) in [
+            // the only mate in one, among many winning moves
+            ("k7/8/1K6/8/8/8/8/7R w - - 0 1", Some("h1h8"), TB_WIN),
+            // the only winning moves: taking the opposition, saving the pawn, and capturing the rook
+            ("5k2/8/6K1/5P2/8/8/8/8 w - - 0 1", Some("g6f6"), TB_WIN),
+            ("8/8/8/8/8/8/2kP4/6K1 w - - 0 1", Some("d2d4"), TB_WIN),
+            ("8/K3p3/5R2/8/8/3k4/8/8 b - - 0 1", Some("e7f6"), TB_WIN),
+            ("8/8/8/8/4k3/8/8/R3K3 b - - 0 1", None, -TB_WIN),
+            ("k7/8/8/8/8/8/P7/7K w - - 0 1", None, 0),
+        ] {
+            let mut pos: Position = parse_fen(fen);
+            let (m, root_score, probes): (u16, i16, u64) = tbs.probe_root(&mut pos).unwrap();
+            assert_eq!(probes, legal_moves(&mut pos).len() as u64, "{fen}");
+            assert_eq!(root_score, score, "{fen}");
+            if let Some(best) = best { assert_eq!(m, uci_to_u16(&pos, best), "{fen}") }
+        }
+    }
+
+    #[test]
+    fn ignores_misnamed_files() {
+        let dir: PathBuf = std::env::temp_dir().join("akimbo_misnamed_tbs");
+        fs::create_dir_all(&dir).unwrap();
+        for name in [".rtbw", "v.rtbw", "KvK.rtbw", "QvK.rtbw", "KXvK.rtbz", "KQvK.rtbw", "KRPvKR.txt"] {
+            fs::write(dir.join(name), []).unwrap();
+        }
+        let tbs: Tablebases = Tablebases::new(&dir.to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tbs.cardinality, 3);
+        assert_eq!(tbs.files.keys().collect::<Vec<_>>(), ["KQvK.rtbw"]);
+    }
+}
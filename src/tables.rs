@@ -1,73 +1,276 @@
-use super::consts::{KILLERS_PER_PLY, MATE_THRESHOLD, MAX_PLY};
+use std::{alloc::{alloc_zeroed, dealloc, Layout}, fs::File, io::{BufReader, BufWriter, Read, Write}, ops::{Deref, DerefMut}, ptr::NonNull};
+use super::{consts::{Bound, VERSION, KILLERS_PER_PLY, TB_THRESHOLD, MAX_PLY, PAWN_HASH_SIZE, HISTORY_MAX}, position::S};
 
+/// A single search result, packed into 12 bytes:
+/// - the lower 32 bits of the zobrist hash, for verification (the upper bits pick the bucket)
+/// - `flags` holds the bound (2 bits), whether the position was in a pv (1 bit) and the search age (5 bits)
 #[derive(Clone, Copy, Default)]
+#[repr(C)]
 pub struct HashEntry {
-    pub key: u16,
+    key: u32,
     pub best_move: u16,
     pub score: i16,
+    pub eval: i16,
     pub depth: i8,
-    pub bound: u8,
+    flags: u8,
+}
+
+impl HashEntry {
+    pub fn new(best_move: u16, score: i16, eval: i16, depth: i8, bound: u8, pv: bool) -> Self {
+        Self { key: 0, best_move, score, eval, depth, flags: bound | (u8::from(pv) << 2) }
+    }
+
+    pub fn bound(&self) -> u8 {
+        self.flags & 3
+    }
+
+    pub fn pv(&self) -> bool {
+        self.flags & 4 > 0
+    }
+
+    fn age(&self) -> u8 {
+        self.flags >> 3
+    }
+
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes: [u8; 12] = [0; 12];
+        bytes[..4].copy_from_slice(&self.key.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.best_move.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.score.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.eval.to_le_bytes());
+        bytes[10] = self.depth as u8;
+        bytes[11] = self.flags;
+        bytes
+    }
+
+    fn from_bytes(b: [u8; 12]) -> Self {
+        Self {
+            key: u32::from_le_bytes([b[0], b[1], b[2], b[3]]), best_move: u16::from_le_bytes([b[4], b[5]]),
+            score: i16::from_le_bytes([b[6], b[7]]), eval: i16::from_le_bytes([b[8], b[9]]), depth: b[10] as i8, flags: b[11],
+        }
+    }
+}
+
+/// Exactly one cache line.
+#[derive(Clone, Copy, Default)]
+#[repr(C, align(64))]
+struct Bucket([HashEntry; ENTRIES_PER_BUCKET]);
+const ENTRIES_PER_BUCKET: usize = 5;
+const _: () = assert!(std::mem::size_of::<Bucket>() == 64);
+const HASH_MAGIC: &[u8; 8] = b"akimboTT";
+
+/// Zeroed allocation of buckets, aligned so that it can be backed by transparent huge pages on linux.
+struct Buckets {
+    ptr: NonNull<Bucket>,
+    len: usize,
+    layout: Layout,
+}
+
+// the allocation is uniquely owned, and only accessed through `&self` / `&mut self`
+unsafe impl Send for Buckets {}
+unsafe impl Sync for Buckets {}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn madvise(addr: *mut std::ffi::c_void, len: usize, advice: i32) -> i32;
+}
+
+impl Buckets {
+    const ALIGN: usize = 2 * 1024 * 1024;
+
+    fn new(len: usize) -> Result<Self, String> {
+        let layout: Layout = Layout::array::<Bucket>(len).and_then(|layout| layout.align_to(Self::ALIGN)).map_err(|err| err.to_string())?;
+        // zero is a valid (empty) bucket
+        let ptr: *mut u8 = unsafe { alloc_zeroed(layout) };
+        let ptr: NonNull<Bucket> = NonNull::new(ptr as *mut Bucket).ok_or(format!("failed to allocate {} MB", layout.size() >> 20))?;
+        #[cfg(target_os = "linux")]
+        unsafe { madvise(ptr.as_ptr() as *mut std::ffi::c_void, layout.size(), 14) }; // MADV_HUGEPAGE, only a hint
+        Ok(Self { ptr, len, layout })
+    }
+}
+
+impl Deref for Buckets {
+    type Target = [Bucket];
+    fn deref(&self) -> &[Bucket] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Buckets {
+    fn deref_mut(&mut self) -> &mut [Bucket] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Buckets {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr() as *mut u8, self.layout) }
+    }
 }
 
 pub struct HashTable {
-    table: Vec<[HashEntry; 8]>,
-    num_buckets: usize,
+    table: Buckets,
+    age: u8,
 }
 
 impl HashTable {
     /// Instantiates a new hash table with size 1mb.
     pub fn new() -> Self {
-        let mut ret: Self = Self { table: Vec::new(), num_buckets: 0 };
-        ret.resize(1);
-        ret
+        Self { table: Buckets::new((1 << 20) / std::mem::size_of::<Bucket>()).expect("1 MB hash table"), age: 0 }
     }
 
-    /// Resizes the hash table to given size **in megabytes**, rounded down to nearest power of 2.
-    pub fn resize(&mut self, mut size: usize) {
-        size = 2usize.pow((size as f64).log2().floor() as u32);
-        self.num_buckets = size * 1024 * 1024 / std::mem::size_of::<[HashEntry; 8]>();
-        self.table = vec![Default::default(); self.num_buckets];
+    /// Resizes the hash table to given size **in megabytes**, freeing the old table first so that
+    /// both are never held at once, and going back to the old size if allocation fails.
+    pub fn resize(&mut self, size: usize) -> Result<(), String> {
+        let num_buckets: usize = size.max(1).checked_mul(1024 * 1024).ok_or("hash size too large")? / std::mem::size_of::<Bucket>();
+        let old_len: usize = self.table.len();
+        self.table = Buckets::new(1)?;
+        self.age = 0;
+        match Buckets::new(num_buckets) {
+            Ok(table) => self.table = table,
+            Err(err) => {
+                if let Ok(table) = Buckets::new(old_len) { self.table = table }
+                return Err(err)
+            }
+        }
+        Ok(())
     }
 
+    /// Zeroes the table, split across all available threads.
     pub fn clear(&mut self) {
-        self.table.iter_mut().for_each(|bucket| *bucket = [HashEntry::default(); 8]);
+        let threads: usize = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size: usize = self.table.len().div_ceil(threads);
+        std::thread::scope(|s| for chunk in self.table.chunks_mut(chunk_size) {
+            s.spawn(|| chunk.fill(Bucket::default()));
+        });
+        self.age = 0;
+    }
+
+    /// Bucket index from the high bits of the hash, which allows for any number of buckets.
+    #[inline]
+    fn index(&self, zobrist: u64) -> usize {
+        ((u128::from(zobrist) * self.table.len() as u128) >> 64) as usize
+    }
+
+    /// Writes all entries of at least `min_depth` to `path`.
+    /// #### Format
+    /// - header: `HASH_MAGIC`, engine version (length prefixed), number of buckets (u64) and age (u8)
+    /// - entry count (u64), followed by that many (bucket index (u64), entry (12 bytes)) pairs
+    pub fn save(&self, path: &str, min_depth: i8) -> Result<usize, String> {
+        let mut out: BufWriter<File> = BufWriter::new(File::create(path).map_err(|err| err.to_string())?);
+        let entries = self.table.iter().enumerate()
+            .flat_map(|(idx, bucket)| bucket.0.iter().map(move |entry| (idx, entry)))
+            .filter(|(_, entry)| entry.bound() != 0 && entry.depth >= min_depth);
+        let count: usize = entries.clone().count();
+        let mut write = |bytes: &[u8]| out.write_all(bytes).map_err(|err| err.to_string());
+        write(HASH_MAGIC)?;
+        write(&[VERSION.len() as u8])?;
+        write(VERSION.as_bytes())?;
+        write(&(self.table.len() as u64).to_le_bytes())?;
+        write(&[self.age])?;
+        write(&(count as u64).to_le_bytes())?;
+        for (idx, entry) in entries {
+            write(&(idx as u64).to_le_bytes())?;
+            write(&entry.to_bytes())?;
+        }
+        out.flush().map_err(|err| err.to_string())?;
+        Ok(count)
+    }
+
+    /// Replaces the table with one written by `save`, provided it was written by this version
+    /// of the engine with the same hash size. The current table is kept if anything is invalid.
+    pub fn load(&mut self, path: &str) -> Result<usize, String> {
+        let mut inp: BufReader<File> = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+        let mut read = |buf: &mut [u8]| inp.read_exact(buf).map_err(|err| format!("truncated file ({err})"));
+        let mut magic: [u8; 8] = [0; 8];
+        read(&mut magic)?;
+        if magic != *HASH_MAGIC { return Err("not a hash file".to_string()) }
+        let mut len: [u8; 1] = [0];
+        read(&mut len)?;
+        let mut version: Vec<u8> = vec![0; usize::from(len[0])];
+        read(&mut version)?;
+        if version != VERSION.as_bytes() {
+            return Err(format!("written by version {}, this is {VERSION}", String::from_utf8_lossy(&version)))
+        }
+        let mut word: [u8; 8] = [0; 8];
+        read(&mut word)?;
+        let num_buckets: usize = u64::from_le_bytes(word) as usize;
+        if num_buckets != self.table.len() {
+            let mb = |n: usize| (n * std::mem::size_of::<Bucket>()) >> 20;
+            return Err(format!("hash size is {} MB, file needs {} MB", mb(self.table.len()), mb(num_buckets)))
+        }
+        let mut age: [u8; 1] = [0];
+        read(&mut age)?;
+        if age[0] > 31 { return Err("corrupt header".to_string()) }
+        read(&mut word)?;
+        let count: u64 = u64::from_le_bytes(word);
+        let mut table: Buckets = Buckets::new(num_buckets)?;
+        let mut bytes: [u8; 12] = [0; 12];
+        for _ in 0..count {
+            read(&mut word)?;
+            read(&mut bytes)?;
+            let idx: usize = u64::from_le_bytes(word) as usize;
+            let entry: HashEntry = HashEntry::from_bytes(bytes);
+            if idx >= num_buckets || entry.bound() == 0 {
+                return Err("corrupt entry".to_string())
+            }
+            let slot: &mut HashEntry = table[idx].0.iter_mut().find(|old| old.bound() == 0).ok_or("overfull bucket")?;
+            *slot = entry;
+        }
+        self.table = table;
+        self.age = age[0];
+        Ok(count as usize)
+    }
+
+    /// Starts a new search, so that entries from previous searches are replaced first.
+    pub fn increment_age(&mut self) {
+        self.age = (self.age + 1) & 31;
     }
 
     /// Push a search result to the hash table.
     /// #### Replacement Scheme
-    /// 1. Prioritise replacing entries for the same position (key) that have lower depth.
+    /// 1. Entries for the same position (key) are replaced unless they are from
+    ///    this search, much deeper, and the new result isn't exact.
     /// 2. Fill empty entries in bucket.
-    /// 3. Replace lowest depth entry in bucket.
-    pub fn push(&mut self, zobrist: u64, best_move: u16, depth: i8, bound: u8, mut score: i16, ply: i16) {
-        let key: u16 = (zobrist >> 48) as u16;
-        let idx: usize = (zobrist as usize) & (self.num_buckets- 1);
-        let bucket: &mut [HashEntry] = &mut self.table[idx];
-        let mut desired_idx: usize = usize::MAX;
-        let mut smallest_depth: i8 = i8::MAX;
-        for (entry_idx, &entry) in bucket.iter().enumerate() {
-            if (entry.key == key && depth > entry.depth) || entry.depth == 0 {
+    /// 3. Replace the entry with lowest depth, less 2 per search since it was written.
+    pub fn push(&mut self, zobrist: u64, mut entry: HashEntry, ply: i16) {
+        let key: u32 = zobrist as u32;
+        let idx: usize = self.index(zobrist);
+        let age: u8 = self.age;
+        let bucket: &mut [HashEntry; ENTRIES_PER_BUCKET] = &mut self.table[idx].0;
+        let mut desired_idx: usize = 0;
+        let mut lowest_value: i16 = i16::MAX;
+        for (entry_idx, old) in bucket.iter().enumerate() {
+            if old.key == key {
+                if entry.bound() != Bound::EXACT && old.age() == age && i16::from(entry.depth) + 3 < i16::from(old.depth) { return }
+                if entry.best_move == 0 { entry.best_move = old.best_move }
+                desired_idx = entry_idx;
+                break
+            }
+            if old.bound() == 0 {
                 desired_idx = entry_idx;
-                break;
+                break
             }
-            if entry.depth < smallest_depth {
-                smallest_depth = entry.depth;
+            let value: i16 = i16::from(old.depth) - 2 * i16::from(age.wrapping_sub(old.age()) & 31);
+            if value < lowest_value {
+                lowest_value = value;
                 desired_idx = entry_idx;
-                continue;
             }
         }
-        score += if score > MATE_THRESHOLD {ply} else if score < -MATE_THRESHOLD {-ply} else {0};
-        bucket[desired_idx] = HashEntry { key, best_move, score, depth, bound };
+        entry.key = key;
+        entry.flags |= age << 3;
+        entry.score += if entry.score > TB_THRESHOLD {ply} else if entry.score < -TB_THRESHOLD {-ply} else {0};
+        bucket[desired_idx] = entry;
     }
 
     /// Probes the hash table for an entry matching the provided hash value, returning first match.
     pub fn probe(&self, zobrist: u64, ply: i16) -> Option<HashEntry> {
-        let key: u16 = (zobrist >> 48) as u16;
-        let idx: usize = (zobrist as usize) & (self.num_buckets - 1);
-        let bucket: &[HashEntry; 8] = &self.table[idx];
-        for entry in bucket {
-            if entry.key == key {
+        let key: u32 = zobrist as u32;
+        let idx: usize = self.index(zobrist);
+        for entry in &self.table[idx].0 {
+            if entry.key == key && entry.bound() != 0 {
                 let mut res: HashEntry = *entry;
-                res.score += if res.score > MATE_THRESHOLD {-ply} else if res.score < -MATE_THRESHOLD {ply} else {0};
+                res.score += if res.score > TB_THRESHOLD {-ply} else if res.score < -TB_THRESHOLD {ply} else {0};
                 return Some(res);
             }
         }
@@ -75,6 +278,36 @@ impl HashTable {
     }
 }
 
+/// Triangular table of principal variations, where the line at each ply is the
+/// best move followed by the line from the next ply.
+pub struct PvTable {
+    moves: [[u16; MAX_PLY as usize]; MAX_PLY as usize],
+    len: [usize; MAX_PLY as usize],
+}
+
+impl PvTable {
+    pub fn new() -> Self {
+        Self { moves: [[0; MAX_PLY as usize]; MAX_PLY as usize], len: [0; MAX_PLY as usize] }
+    }
+
+    pub fn clear(&mut self, ply: usize) {
+        self.len[ply] = 0;
+    }
+
+    /// Sets the line at this ply to the move followed by the line from the next ply.
+    pub fn update(&mut self, ply: usize, m: u16) {
+        let (line, rest): (&mut [[u16; MAX_PLY as usize]], &mut [[u16; MAX_PLY as usize]]) = self.moves.split_at_mut(ply + 1);
+        let len: usize = self.len[ply + 1];
+        line[ply][0] = m;
+        line[ply][1..=len].copy_from_slice(&rest[0][..len]);
+        self.len[ply] = len + 1;
+    }
+
+    pub fn line(&self, ply: usize) -> &[u16] {
+        &self.moves[ply][..self.len[ply]]
+    }
+}
+
 pub struct KillerTable(pub [[u16; KILLERS_PER_PLY]; MAX_PLY as usize]);
 impl KillerTable {
     pub fn push(&mut self, m: u16, p: i16) {
@@ -88,3 +321,169 @@ impl KillerTable {
         self.0.iter_mut().for_each(|bucket| *bucket = [0; KILLERS_PER_PLY]);
     }
 }
+
+/// Moves the entry towards the bonus, keeping it within +-HISTORY_MAX.
+#[inline]
+fn gravity(entry: &mut i16, bonus: i16) {
+    let (e, b): (i32, i32) = (i32::from(*entry), i32::from(bonus));
+    *entry = (e + b - e * b.abs() / HISTORY_MAX) as i16;
+}
+
+/// Butterfly history, indexed by side, from and to squares.
+pub struct HistoryTable(Box<[[[i16; 64]; 64]; 2]>);
+impl HistoryTable {
+    pub fn new() -> Self {
+        Self(Box::new([[[0; 64]; 64]; 2]))
+    }
+
+    pub fn get(&self, side: usize, m: u16) -> i16 {
+        self.0[side][usize::from((m >> 6) & 63)][usize::from(m & 63)]
+    }
+
+    pub fn update(&mut self, side: usize, m: u16, bonus: i16) {
+        gravity(&mut self.0[side][usize::from((m >> 6) & 63)][usize::from(m & 63)], bonus);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.iter_mut().flatten().for_each(|row| *row = [0; 64]);
+    }
+}
+
+/// Last quiet move to refute each previous move, indexed by side, then piece and destination of the previous move.
+pub struct CounterMoveTable([[[u16; 64]; 6]; 2]);
+impl CounterMoveTable {
+    pub fn new() -> Self {
+        Self([[[0; 64]; 6]; 2])
+    }
+
+    pub fn get(&self, side: usize, (piece, to): (usize, usize)) -> u16 {
+        self.0[side][piece][to]
+    }
+
+    pub fn push(&mut self, side: usize, (piece, to): (usize, usize), m: u16) {
+        self.0[side][piece][to] = m;
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = [[[0; 64]; 6]; 2];
+    }
+}
+
+/// Continuation history, indexed by side, the piece and destination of an earlier move,
+/// then the piece and destination of the current move.
+pub struct ContinuationTable(Vec<[[i16; 64]; 6]>);
+impl ContinuationTable {
+    pub fn new() -> Self {
+        Self(vec![[[0; 64]; 6]; 2 * 6 * 64])
+    }
+
+    pub fn get(&self, side: usize, (prev_piece, prev_to): (usize, usize), (piece, to): (usize, usize)) -> i16 {
+        self.0[384 * side + 64 * prev_piece + prev_to][piece][to]
+    }
+
+    pub fn update(&mut self, side: usize, (prev_piece, prev_to): (usize, usize), (piece, to): (usize, usize), bonus: i16) {
+        gravity(&mut self.0[384 * side + 64 * prev_piece + prev_to][piece][to], bonus);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.iter_mut().for_each(|entry| *entry = [[0; 64]; 6]);
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PawnEntry {
+    pub key: u64,
+    pub score: S,
+    pub passers: [u64; 2],
+}
+
+/// Caches the king-independent pawn structure evaluation, indexed by pawn hash.
+pub struct PawnHashTable(Vec<PawnEntry>);
+impl PawnHashTable {
+    pub fn new() -> Self {
+        Self(vec![PawnEntry::default(); PAWN_HASH_SIZE])
+    }
+
+    pub fn push(&mut self, entry: PawnEntry) {
+        self.0[(entry.key as usize) & (PAWN_HASH_SIZE - 1)] = entry;
+    }
+
+    pub fn probe(&self, key: u64) -> Option<PawnEntry> {
+        let entry: PawnEntry = self.0[(key as usize) & (PAWN_HASH_SIZE - 1)];
+        if entry.key == key {Some(entry)} else {None}
+    }
+
+    pub fn clear(&mut self) {
+        self.0.iter_mut().for_each(|entry| *entry = PawnEntry::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path: String = temp_path("akimbo_tt_round_trip.bin");
+        let mut table: HashTable = HashTable::new();
+        table.increment_age();
+        let keys: [u64; 4] = [0x0123_4567_89AB_CDEF, 0xFEDC_BA98_7654_3210, 0x0F0F_0F0F_F0F0_F0F0, 0x1111_2222_3333_4444];
+        for (i, &key) in keys.iter().enumerate() {
+            table.push(key, HashEntry::new(100 + i as u16, 10 * i as i16 - 15, -(i as i16), 2 * i as i8, [Bound::LOWER, Bound::UPPER, Bound::EXACT][i % 3], i % 2 == 0), 0);
+        }
+        assert_eq!(table.save(&path, 2), Ok(3));
+
+        let mut loaded: HashTable = HashTable::new();
+        let result: Result<usize, String> = loaded.load(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(result, Ok(3));
+        assert_eq!(loaded.age, table.age);
+        assert!(loaded.probe(keys[0], 0).is_none());
+        for &key in &keys[1..] {
+            let (a, b): (HashEntry, HashEntry) = (table.probe(key, 0).unwrap(), loaded.probe(key, 0).unwrap());
+            assert_eq!(a.to_bytes(), b.to_bytes());
+        }
+    }
+
+    #[test]
+    fn load_rejects_bad_headers() {
+        let path: String = temp_path("akimbo_tt_bad_headers.bin");
+        let mut table: HashTable = HashTable::new();
+        table.push(42 << 40, HashEntry::new(7, 8, 9, 10, Bound::EXACT, true), 0);
+        table.save(&path, 0).unwrap();
+        let good: Vec<u8> = std::fs::read(&path).unwrap();
+
+        let mut bigger: HashTable = HashTable::new();
+        bigger.resize(2).unwrap();
+        let mut version: Vec<u8> = good.clone();
+        version[9] ^= 1;
+        let mut corrupt: Vec<u8> = good.clone();
+        corrupt[good.len() - 1] = 0;
+        for (bytes, target, err) in [
+            (b"notahashfile".to_vec(), &mut HashTable::new(), "not a hash file"),
+            (version, &mut HashTable::new(), "written by version"),
+            (good.clone(), &mut bigger, "hash size is 2 MB, file needs 1 MB"),
+            (good[..good.len() - 6].to_vec(), &mut HashTable::new(), "truncated file"),
+            (corrupt, &mut HashTable::new(), "corrupt entry"),
+        ] {
+            std::fs::write(&path, bytes).unwrap();
+            target.push(1, HashEntry::new(1, 2, 3, 4, Bound::UPPER, false), 0);
+            assert!(target.load(&path).is_err_and(|e| e.starts_with(err)), "{err}");
+            // the old table is kept
+            assert!(target.probe(1, 0).is_some(), "{err}");
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn failed_resize_keeps_old_size() {
+        let mut table: HashTable = HashTable::new();
+        table.resize(2).unwrap();
+        assert!(table.resize(usize::MAX / (1 << 20)).is_err());
+        assert_eq!(table.table.len(), 2 * (1 << 20) / std::mem::size_of::<Bucket>());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{fs::File, io::{BufRead, BufReader, Write}, time::Instant};
+use super::{consts::*, position::{Position, S}, eval::{param, Trace}, parse_fen};
+
+const LEARNING_RATE: f64 = 1.0;
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+const REPORT_EVERY: usize = 50;
+
+/// Tapered parameters written out after tuning, in `param` order, excluding the PST.
+const PARAMS: [(&str, usize, &[S]); 22] = [
+    ("DOUBLED", param::DOUBLED, &[DOUBLED]),
+    ("ISOLATED", param::ISOLATED, &[ISOLATED]),
+    ("BACKWARD", param::BACKWARD, &[BACKWARD]),
+    ("PASSED", param::PASSED, &PASSED),
+    ("CONNECTED", param::CONNECTED, &CONNECTED),
+    ("PASSER_OWN_KING", param::PASSER_OWN_KING, &[PASSER_OWN_KING]),
+    ("PASSER_OPP_KING", param::PASSER_OPP_KING, &[PASSER_OPP_KING]),
+    ("UNSTOPPABLE", param::UNSTOPPABLE, &[UNSTOPPABLE]),
+    ("KNIGHT_MOBILITY", param::KNIGHT_MOBILITY, &KNIGHT_MOBILITY),
+    ("BISHOP_MOBILITY", param::BISHOP_MOBILITY, &BISHOP_MOBILITY),
+    ("ROOK_MOBILITY", param::ROOK_MOBILITY, &ROOK_MOBILITY),
+    ("QUEEN_MOBILITY", param::QUEEN_MOBILITY, &QUEEN_MOBILITY),
+    ("BISHOP_PAIR", param::BISHOP_PAIR, &[BISHOP_PAIR]),
+    ("ROOK_OPEN", param::ROOK_OPEN, &[ROOK_OPEN]),
+    ("ROOK_SEMI_OPEN", param::ROOK_SEMI_OPEN, &[ROOK_SEMI_OPEN]),
+    ("KNIGHT_OUTPOST", param::KNIGHT_OUTPOST, &[KNIGHT_OUTPOST]),
+    ("BISHOP_OUTPOST", param::BISHOP_OUTPOST, &[BISHOP_OUTPOST]),
+    ("THREAT_BY_PAWN", param::THREAT_BY_PAWN, &[THREAT_BY_PAWN]),
+    ("THREAT_BY_MINOR", param::THREAT_BY_MINOR, &[THREAT_BY_MINOR]),
+    ("THREAT_BY_ROOK", param::THREAT_BY_ROOK, &[THREAT_BY_ROOK]),
+    ("PAWN_SHIELD", param::PAWN_SHIELD, &PAWN_SHIELD),
+    ("PAWN_STORM", param::PAWN_STORM, &PAWN_STORM),
+];
+
+/// Dense coefficients of a single evaluation, white relative.
+struct Coefficients {
+    coeffs: Vec<i16>,
+    fixed: S,
+}
+
+impl Trace for Coefficients {
+    fn add(&mut self, side: usize, param: usize, coeff: i16) {
+        self.coeffs[param] += SIDE_FACTOR[side] * coeff;
+    }
+
+    fn fixed(&mut self, side: usize, score: S) {
+        self.fixed += SIDE_FACTOR[side] * score;
+    }
+}
+
+/// A training position, with only the nonzero coefficients kept.
+struct Entry {
+    coeffs: Vec<(u16, i16)>,
+    fixed: [f64; 2],
+    phase: f64,
+    result: f64,
+}
+
+impl Entry {
+    fn eval(&self, params: &[[f64; 2]]) -> f64 {
+        let (mut mg, mut eg): (f64, f64) = (self.fixed[0], self.fixed[1]);
+        for &(idx, coeff) in &self.coeffs {
+            mg += f64::from(coeff) * params[idx as usize][0];
+            eg += f64::from(coeff) * params[idx as usize][1];
+        }
+        self.phase * mg + (1.0 - self.phase) * eg
+    }
+}
+
+#[inline]
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+fn error(entries: &[Entry], params: &[[f64; 2]], k: f64) -> f64 {
+    entries.iter().map(|entry| (entry.result - sigmoid(k, entry.eval(params))).powi(2)).sum::<f64>() / entries.len() as f64
+}
+
+/// Result for white, from either `fen | score | result` or `fen [result]` lines.
+fn parse_line(line: &str) -> Option<(&str, f64)> {
+    let (fen, result): (&str, &str) = if line.contains('|') {
+        (line.split('|').next()?, line.rsplit('|').next()?)
+    } else {
+        let start: usize = line.rfind('[')?;
+        (&line[..start], line[start + 1..].trim_end_matches(|c: char| c == ']' || c.is_whitespace()))
+    };
+    let result: f64 = match result.trim().trim_matches('"') {
+        "1-0" => 1.0,
+        "0-1" => 0.0,
+        "1/2-1/2" => 0.5,
+        x => x.parse().ok()?,
+    };
+    Some((fen.trim(), result))
+}
+
+fn load(path: &str) -> std::io::Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line: String = line?;
+        let Some((fen, result)) = parse_line(&line) else { continue };
+        let pos: Position = parse_fen(fen);
+        let mut trace: Coefficients = Coefficients { coeffs: vec![0; param::COUNT], fixed: S(0, 0) };
+        pos.trace_eval(&mut trace);
+        entries.push(Entry {
+            coeffs: trace.coeffs.iter().enumerate().filter(|(_, &c)| c != 0).map(|(i, &c)| (i as u16, c)).collect(),
+            fixed: [f64::from(trace.fixed.0), f64::from(trace.fixed.1)],
+            phase: f64::from(std::cmp::min(pos.phase as i32, TPHASE)) / f64::from(TPHASE),
+            result,
+        });
+    }
+    Ok(entries)
+}
+
+/// Ternary search for the sigmoid scaling constant that best fits the current evaluation.
+fn optimal_k(entries: &[Entry], params: &[[f64; 2]]) -> f64 {
+    let (mut lo, mut hi): (f64, f64) = (0.0, 10.0);
+    for _ in 0..50 {
+        let (m1, m2): (f64, f64) = (lo + (hi - lo) / 3.0, hi - (hi - lo) / 3.0);
+        if error(entries, params, m1) < error(entries, params, m2) {hi = m2} else {lo = m1}
+    }
+    (lo + hi) / 2.0
+}
+
+fn initial_params() -> Vec<[f64; 2]> {
+    let mut params: Vec<[f64; 2]> = vec![[0.0; 2]; param::COUNT];
+    let mut set = |start: usize, values: &[S]| for (i, s) in values.iter().enumerate() {
+        params[start + i] = [f64::from(s.0), f64::from(s.1)];
+    };
+    for (piece, table) in PST.iter().enumerate() { set(param::PST + 64 * piece, table) }
+    for (_, start, values) in PARAMS { set(start, values) }
+    params
+}
+
+fn write_params(out: &mut impl Write, params: &[[f64; 2]], k: f64, err: f64) -> std::io::Result<()> {
+    let s = |p: [f64; 2]| format!("S({}, {})", p[0].round() as i16, p[1].round() as i16);
+    let list = |start: usize, len: usize| (start..start + len).map(|i| s(params[i])).collect::<Vec<String>>().join(", ");
+    writeln!(out, "// tuned with K = {k:.4}, error = {err:.6}")?;
+    writeln!(out, "pub static PST: [[S; 64]; 6] = [")?;
+    for piece in 0..6 { writeln!(out, "    [{}],", list(param::PST + 64 * piece, 64))? }
+    writeln!(out, "];")?;
+    for (name, start, values) in PARAMS {
+        match values.len() {
+            1 => writeln!(out, "pub const {name}: S = {};", s(params[start]))?,
+            len => writeln!(out, "pub const {name}: [S; {len}] = [{}];", list(start, len))?,
+        }
+    }
+    Ok(())
+}
+
+/// Texel tuning of every tapered parameter with full batch Adam, writing the
+/// results as Rust source to `out`, or stdout if not given.
+pub fn run(path: &str, epochs: usize, out: Option<&str>) -> std::io::Result<()> {
+    let timer: Instant = Instant::now();
+    let entries: Vec<Entry> = load(path)?;
+    if entries.is_empty() {
+        println!("info string no positions found");
+        return Ok(())
+    }
+    let mut params: Vec<[f64; 2]> = initial_params();
+    let k: f64 = optimal_k(&entries, &params);
+    println!("info string positions {} K {k:.4} error {:.6} time {}ms", entries.len(), error(&entries, &params, k), timer.elapsed().as_millis());
+
+    let (mut momentum, mut velocity): (Vec<[f64; 2]>, Vec<[f64; 2]>) = (vec![[0.0; 2]; param::COUNT], vec![[0.0; 2]; param::COUNT]);
+    let scale: f64 = k * 10f64.ln() / 400.0;
+    for epoch in 1..=epochs {
+        let mut gradient: Vec<[f64; 2]> = vec![[0.0; 2]; param::COUNT];
+        for entry in &entries {
+            let sig: f64 = sigmoid(k, entry.eval(&params));
+            let grad: f64 = (sig - entry.result) * sig * (1.0 - sig) * scale;
+            for &(idx, coeff) in &entry.coeffs {
+                gradient[idx as usize][0] += grad * entry.phase * f64::from(coeff);
+                gradient[idx as usize][1] += grad * (1.0 - entry.phase) * f64::from(coeff);
+            }
+        }
+        for (i, grad) in gradient.iter().enumerate() {
+            for j in 0..2 {
+                let g: f64 = 2.0 * grad[j] / entries.len() as f64;
+                momentum[i][j] = BETA1 * momentum[i][j] + (1.0 - BETA1) * g;
+                velocity[i][j] = BETA2 * velocity[i][j] + (1.0 - BETA2) * g * g;
+                let m: f64 = momentum[i][j] / (1.0 - BETA1.powi(epoch as i32));
+                let v: f64 = velocity[i][j] / (1.0 - BETA2.powi(epoch as i32));
+                params[i][j] -= LEARNING_RATE * m / (v.sqrt() + EPSILON);
+            }
+        }
+        if epoch % REPORT_EVERY == 0 || epoch == epochs {
+            println!("info string epoch {epoch} error {:.6} time {}ms", error(&entries, &params, k), timer.elapsed().as_millis());
+        }
+    }
+
+    let err: f64 = error(&entries, &params, k);
+    match out {
+        Some(path) => write_params(&mut File::create(path)?, &params, k, err),
+        None => write_params(&mut std::io::stdout(), &params, k, err),
+    }
+}
@@ -1,4 +1,7 @@
+use super::consts::{KNIGHT, BISHOP, ROOK, QUEEN, KING, KNIGHT_ATTACKS, KING_ATTACKS};
+
 pub static ZVALS: ZobristVals = zvals_init();
+pub static CUCKOO: Cuckoo = cuckoo_init(&zvals_init());
 
 /// Holds random hash values for each aspect of the board position
 pub struct ZobristVals {
@@ -44,3 +47,99 @@ const fn zvals_init() -> ZobristVals {
     while idx < 14 {seed = xor_shift(seed); vals.en_passant[idx - 6] = seed; idx += 1;}
     vals
 }
+
+const CUCKOO_SIZE: usize = 8192;
+
+/// Cuckoo hash table of every reversible (non-pawn) move, keyed by the change in zobrist hash
+/// it causes, so that a move leading back to an earlier position can be found from the two hashes.
+pub struct Cuckoo {
+    keys: [u64; CUCKOO_SIZE],
+    moves: [u16; CUCKOO_SIZE],
+}
+
+const fn h1(key: u64) -> usize {
+    (key & 0x1FFF) as usize
+}
+
+const fn h2(key: u64) -> usize {
+    ((key >> 16) & 0x1FFF) as usize
+}
+
+impl Cuckoo {
+    /// The move (from and to squares only) that changes the hash by `key`, if any.
+    pub fn probe(&self, key: u64) -> Option<u16> {
+        [h1(key), h2(key)].into_iter().find(|&i| self.keys[i] == key).map(|i| self.moves[i])
+    }
+}
+
+/// Whether a piece can move between two squares on an empty board.
+const fn empty_board_move(piece: usize, s1: usize, s2: usize) -> bool {
+    let (r1, f1, r2, f2): (usize, usize, usize, usize) = (s1 / 8, s1 % 8, s2 / 8, s2 % 8);
+    let line: bool = r1 == r2 || f1 == f2;
+    let diagonal: bool = r1.abs_diff(r2) == f1.abs_diff(f2);
+    match piece {
+        KNIGHT => KNIGHT_ATTACKS[s1] & (1 << s2) > 0,
+        BISHOP => diagonal,
+        ROOK => line,
+        QUEEN => diagonal || line,
+        KING => KING_ATTACKS[s1] & (1 << s2) > 0,
+        _ => false,
+    }
+}
+
+/// Initialises CUCKOO, by inserting both colours' moves for every non-pawn piece.
+const fn cuckoo_init(vals: &ZobristVals) -> Cuckoo {
+    let mut table: Cuckoo = Cuckoo { keys: [0; CUCKOO_SIZE], moves: [0; CUCKOO_SIZE] };
+    let mut side: usize = 0;
+    while side < 2 {
+        let mut piece: usize = KNIGHT;
+        while piece <= KING {
+            let mut s1: usize = 0;
+            while s1 < 64 {
+                let mut s2: usize = s1 + 1;
+                while s2 < 64 {
+                    if empty_board_move(piece, s1, s2) {
+                        let mut m: u16 = ((s1 << 6) | s2) as u16;
+                        let mut key: u64 = vals.pieces[side][piece][s1] ^ vals.pieces[side][piece][s2] ^ vals.side;
+                        let mut i: usize = h1(key);
+                        // insert, evicting into the other slot of the displaced entry until an empty one is found
+                        loop {
+                            let (old_key, old_move): (u64, u16) = (table.keys[i], table.moves[i]);
+                            table.keys[i] = key;
+                            table.moves[i] = m;
+                            if old_move == 0 { break }
+                            (key, m) = (old_key, old_move);
+                            i = if i == h1(key) {h2(key)} else {h1(key)};
+                        }
+                    }
+                    s2 += 1;
+                }
+                s1 += 1;
+            }
+            piece += 1;
+        }
+        side += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every reversible non-pawn move gets a slot that probes back to it.
+    #[test]
+    fn cuckoo_holds_every_reversible_move() {
+        assert_eq!(CUCKOO.moves.iter().filter(|&&m| m != 0).count(), 3668);
+        for side in 0..2 {
+            for piece in KNIGHT..=KING {
+                for s1 in 0..64 {
+                    for s2 in (s1 + 1..64).filter(|&s2| empty_board_move(piece, s1, s2)) {
+                        let key: u64 = ZVALS.pieces[side][piece][s1] ^ ZVALS.pieces[side][piece][s2] ^ ZVALS.side;
+                        assert_eq!(CUCKOO.probe(key), Some(((s1 << 6) | s2) as u16));
+                    }
+                }
+            }
+        }
+    }
+}
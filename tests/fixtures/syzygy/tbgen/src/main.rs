@@ -0,0 +1,132 @@
+//! Generates the small Syzygy tables in `tests/fixtures/syzygy`, independently of akimbo's own
+//! move generation and probing code, so the probing tests check the decoder against a separate encoder.
+//!
+//! Usage: `cargo run --release -- <output dir>` (takes about ten minutes, mostly solving KRvKP).
+mod solve;
+mod write;
+use solve::*;
+use std::collections::HashMap;
+
+fn code(p: Pc) -> u8 {
+    (p.kind + 1) | (p.color << 3)
+}
+
+/// FEN of a solver position, for error messages
+pub fn fen(mat: &[Pc], sq: &[u8], stm: u8) -> String {
+    let mut board = [' '; 64];
+    for (p, &s) in mat.iter().zip(sq) {
+        let c = "pnbrqk".as_bytes()[p.kind as usize] as char;
+        board[s as usize] = if p.color == 0 { c.to_ascii_uppercase() } else { c };
+    }
+    let mut out = String::new();
+    for r in (0..8).rev() {
+        let mut empty = 0;
+        for f in 0..8 {
+            let c = board[r * 8 + f];
+            if c == ' ' {
+                empty += 1;
+            } else {
+                if empty > 0 {
+                    out.push_str(&empty.to_string());
+                }
+                empty = 0;
+                out.push(c);
+            }
+        }
+        if empty > 0 {
+            out.push_str(&empty.to_string());
+        }
+        if r > 0 {
+            out.push('/');
+        }
+    }
+    format!("{out} {} - - 0 1", if stm == 0 { 'w' } else { 'b' })
+}
+
+fn pc(color: u8, kind: u8) -> Pc {
+    Pc { color, kind }
+}
+
+fn emit(solver: &mut Solver, name: &str, mat: Vec<Pc>, order: Vec<Pc>, dtz_stm: [u8; 4], dir: &str) {
+    let t = solver.get(&mat);
+    let n = mat.len();
+    let pawns = mat.iter().any(|p| p.kind == P);
+    let files = if pawns { 4 } else { 1 };
+    let codes: Vec<u8> = order.iter().map(|&p| code(p)).collect();
+    let lays: Vec<write::Layout> = (0..files).map(|f| write::layout(&codes, pawns, f)).collect();
+    // values[dtz][file][side]
+    let mut vals: Vec<Vec<Vec<Vec<Option<u16>>>>> = (0..2)
+        .map(|_| (0..files).map(|f| (0..2).map(|_| vec![None; lays[f].size as usize]).collect()).collect())
+        .collect();
+    let lead_code = codes[0];
+    for idx in 0..t.wdl.len() {
+        let w = t.wdl[idx];
+        if w == ILLEGAL {
+            continue;
+        }
+        let (stm, sqa) = decode(idx, n);
+        let pieces: Vec<(u8, usize)> = mat.iter().zip(&sqa[..n]).map(|(&p, &s)| (code(p), s as usize)).collect();
+        let file = if pawns {
+            let lead = pieces.iter().filter(|x| x.0 == lead_code).max_by_key(|x| pawn_map_pub(x.1)).unwrap().1;
+            (lead & 7).min(7 - (lead & 7))
+        } else {
+            0
+        };
+        let i = write::encode(&lays[file], &pieces) as usize;
+        let wdl_v = (w + 2) as u16;
+        let dtz_v = if w == 0 { 0 } else { t.dtz[idx].unsigned_abs() - 1 };
+        for (k, v) in [(0, wdl_v), (1, dtz_v)] {
+            // draws are never looked up in dtz tables
+            if k == 1 && w == 0 {
+                continue;
+            }
+            let slot = &mut vals[k][file][stm as usize][i];
+            if let Some(old) = *slot {
+                if old != v && !(k == 1 && w == 0) {
+                    panic!("{name}: collision at {} ({old} vs {v}) {}", i, fen(&mat, &sqa[..n], stm));
+                }
+            }
+            if k == 1 && w == 0 && slot.is_some() {
+                continue;
+            }
+            *slot = Some(v);
+        }
+    }
+    for (k, vals) in vals.iter().enumerate() {
+        let mut items: Vec<Vec<write::Pairs>> = Vec::new();
+        for f in 0..files {
+            let mut sides = Vec::new();
+            let side_list: Vec<usize> = if k == 0 { vec![0, 1] } else { vec![dtz_stm[f] as usize] };
+            for s in side_list {
+                let v = &vals[f][s];
+                let mut last = 0u16;
+                let filled: Vec<u16> = v.iter().map(|x| { if let Some(y) = x { last = *y } last }).collect();
+                let flags = if k == 1 { s as u8 | 4 | 8 } else { 0 };
+                sides.push(write::compress(&filled, flags));
+            }
+            items.push(sides);
+        }
+        let pcodes: Vec<[u8; 2]> = codes.iter().map(|&c| [c, c]).collect();
+        let ext = if k == 0 { "rtbw" } else { "rtbz" };
+        write::write_file(&format!("{dir}/{name}.{ext}"), k == 1, pawns, &pcodes, &items);
+    }
+}
+
+pub fn pawn_map_pub(sq: usize) -> usize {
+    let (f, r) = (sq & 7, sq >> 3);
+    if f <= 3 { 47 - 2 * (6 * f + r - 1) } else { 46 - 2 * (6 * (7 - f) + r - 1) }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let dir = args.get(1).cloned().unwrap_or("out".into());
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut solver = Solver { memo: HashMap::new() };
+    let (wk, bk) = (pc(0, K), pc(1, K));
+    emit(&mut solver, "KQvK", vec![wk, pc(0, Q), bk], vec![wk, pc(0, Q), bk], [0; 4], &dir);
+    emit(&mut solver, "KRvK", vec![wk, pc(0, R), bk], vec![pc(0, R), wk, bk], [1; 4], &dir);
+    emit(&mut solver, "KBvK", vec![wk, pc(0, B), bk], vec![wk, pc(0, B), bk], [0; 4], &dir);
+    emit(&mut solver, "KNvK", vec![wk, pc(0, N), bk], vec![wk, pc(0, N), bk], [0; 4], &dir);
+    emit(&mut solver, "KPvK", vec![wk, pc(0, P), bk], vec![pc(0, P), wk, bk], [0, 1, 0, 1], &dir);
+    emit(&mut solver, "KRvKP", vec![wk, pc(0, R), bk, pc(1, P)], vec![pc(1, P), wk, pc(0, R), bk], [1, 0, 1, 0], &dir);
+}
@@ -0,0 +1,496 @@
+//! Retrograde solver for endgames of up to 4 pieces, without castling or en passant.
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+pub const P: u8 = 0;
+pub const N: u8 = 1;
+pub const B: u8 = 2;
+pub const R: u8 = 3;
+pub const Q: u8 = 4;
+pub const K: u8 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
+pub struct Pc {
+    pub color: u8,
+    pub kind: u8,
+}
+
+/// canonical ordering: white first, then by kind descending (K Q R B N P)
+pub fn sort_key(p: &Pc) -> (u8, u8) {
+    (p.color, 5 - p.kind)
+}
+
+pub const ILLEGAL: i8 = i8::MIN;
+
+pub struct Solved {
+    /// per naive index, -2/0/2 or ILLEGAL
+    pub wdl: Vec<i8>,
+    /// signed plies, 0 for draws
+    pub dtz: Vec<i16>,
+}
+
+pub fn naive_idx(stm: u8, sq: &[u8]) -> usize {
+    let mut idx = 0;
+    for &s in sq.iter().rev() {
+        idx = idx * 64 + s as usize;
+    }
+    idx * 2 + stm as usize
+}
+
+pub fn decode(mut idx: usize, n: usize) -> (u8, [u8; 4]) {
+    let stm = (idx % 2) as u8;
+    idx /= 2;
+    let mut sq = [0u8; 4];
+    for s in sq.iter_mut().take(n) {
+        *s = (idx % 64) as u8;
+        idx /= 64;
+    }
+    (stm, sq)
+}
+
+fn on(f: i32, r: i32) -> bool {
+    (0..8).contains(&f) && (0..8).contains(&r)
+}
+
+const KDIRS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+const NDIRS: [(i32, i32); 8] = [(1, 2), (2, 1), (-1, 2), (-2, 1), (1, -2), (2, -1), (-1, -2), (-2, -1)];
+
+fn dirs(kind: u8) -> (&'static [(i32, i32)], bool) {
+    match kind {
+        N => (&NDIRS, false),
+        B => (&KDIRS[4..], true),
+        R => (&KDIRS[..4], true),
+        Q => (&KDIRS, true),
+        K => (&KDIRS, false),
+        _ => unreachable!(),
+    }
+}
+
+/// does piece `p` on `from` attack `target`, given occupancy
+fn attacks(p: Pc, from: u8, target: u8, occ: u64) -> bool {
+    let (f, r) = ((from & 7) as i32, (from >> 3) as i32);
+    let (tf, tr) = ((target & 7) as i32, (target >> 3) as i32);
+    if p.kind == P {
+        let dir = if p.color == 0 { 1 } else { -1 };
+        return tr == r + dir && (tf - f).abs() == 1;
+    }
+    let (ds, slide) = dirs(p.kind);
+    for &(df, dr) in ds {
+        let (mut cf, mut cr) = (f + df, r + dr);
+        while on(cf, cr) {
+            if cf == tf && cr == tr {
+                return true;
+            }
+            if !slide || occ & (1u64 << (cr * 8 + cf)) != 0 {
+                break;
+            }
+            cf += df;
+            cr += dr;
+        }
+    }
+    false
+}
+
+/// is the king of `color` attacked, `alive` marks pieces still on the board
+pub fn in_check(mat: &[Pc], sq: &[u8], alive: &[bool], color: u8) -> bool {
+    let mut occ = 0u64;
+    for i in 0..mat.len() {
+        if alive[i] {
+            occ |= 1 << sq[i];
+        }
+    }
+    let king = (0..mat.len()).find(|&i| alive[i] && mat[i].color == color && mat[i].kind == K).unwrap();
+    (0..mat.len()).any(|i| alive[i] && mat[i].color != color && attacks(mat[i], sq[i], sq[king], occ))
+}
+
+pub fn legal_position(mat: &[Pc], sq: &[u8], stm: u8) -> bool {
+    let n = mat.len();
+    for i in 0..n {
+        for j in 0..i {
+            if sq[i] == sq[j] {
+                return false;
+            }
+        }
+        if mat[i].kind == P && (sq[i] < 8 || sq[i] >= 56) {
+            return false;
+        }
+    }
+    !in_check(mat, sq, &[true; 4][..n], 1 - stm)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Mv {
+    pub piece: usize,
+    pub to: u8,
+    pub capture: Option<usize>,
+    pub promo: Option<u8>,
+}
+
+impl Mv {
+    pub fn zeroing(&self, mat: &[Pc]) -> bool {
+        self.capture.is_some() || mat[self.piece].kind == P
+    }
+}
+
+/// legal moves for side `stm`
+pub fn gen_moves(mat: &[Pc], sq: &[u8], stm: u8) -> Vec<Mv> {
+    let n = mat.len();
+    let mut occ = 0u64;
+    let mut at = [usize::MAX; 64];
+    for i in 0..n {
+        occ |= 1 << sq[i];
+        at[sq[i] as usize] = i;
+    }
+    let mut pseudo: Vec<Mv> = Vec::new();
+    for i in 0..n {
+        let p = mat[i];
+        if p.color != stm {
+            continue;
+        }
+        let (f, r) = ((sq[i] & 7) as i32, (sq[i] >> 3) as i32);
+        if p.kind == P {
+            let dir = if p.color == 0 { 1 } else { -1 };
+            let last = if p.color == 0 { 7 } else { 0 };
+            let start = if p.color == 0 { 1 } else { 6 };
+            let mut targets: Vec<(u8, Option<usize>)> = Vec::new();
+            let one = (r + dir) * 8 + f;
+            if occ & (1 << one) == 0 {
+                targets.push((one as u8, None));
+                let two = (r + 2 * dir) * 8 + f;
+                if r == start && occ & (1 << two) == 0 {
+                    targets.push((two as u8, None));
+                }
+            }
+            for df in [-1, 1] {
+                if on(f + df, r + dir) {
+                    let t = ((r + dir) * 8 + f + df) as usize;
+                    if at[t] != usize::MAX && mat[at[t]].color != stm {
+                        targets.push((t as u8, Some(at[t])));
+                    }
+                }
+            }
+            for (t, cap) in targets {
+                if (t >> 3) as i32 == last {
+                    for promo in [Q, R, B, N] {
+                        pseudo.push(Mv { piece: i, to: t, capture: cap, promo: Some(promo) });
+                    }
+                } else {
+                    pseudo.push(Mv { piece: i, to: t, capture: cap, promo: None });
+                }
+            }
+            continue;
+        }
+        let (ds, slide) = dirs(p.kind);
+        for &(df, dr) in ds {
+            let (mut cf, mut cr) = (f + df, r + dr);
+            while on(cf, cr) {
+                let t = (cr * 8 + cf) as usize;
+                if at[t] != usize::MAX {
+                    // kings are never captured in legal positions
+                    if mat[at[t]].color != stm && mat[at[t]].kind != K {
+                        pseudo.push(Mv { piece: i, to: t as u8, capture: Some(at[t]), promo: None });
+                    }
+                    break;
+                }
+                pseudo.push(Mv { piece: i, to: t as u8, capture: None, promo: None });
+                if !slide {
+                    break;
+                }
+                cf += df;
+                cr += dr;
+            }
+        }
+    }
+    pseudo
+        .into_iter()
+        .filter(|m| {
+            let mut s2 = [0u8; 4];
+            s2[..n].copy_from_slice(sq);
+            s2[m.piece] = m.to;
+            let mut alive = [true; 4];
+            if let Some(c) = m.capture {
+                alive[c] = false;
+            }
+            !in_check(mat, &s2[..n], &alive[..n], stm)
+        })
+        .collect()
+}
+
+/// child position after a capture or promotion, canonicalised: (material, squares)
+pub fn child(mat: &[Pc], sq: &[u8], m: &Mv) -> (Vec<Pc>, Vec<u8>) {
+    let mut pcs: Vec<(Pc, u8)> = Vec::new();
+    for i in 0..mat.len() {
+        if Some(i) == m.capture {
+            continue;
+        }
+        let mut p = mat[i];
+        let mut s = sq[i];
+        if i == m.piece {
+            s = m.to;
+            if let Some(k) = m.promo {
+                p.kind = k;
+            }
+        }
+        pcs.push((p, s));
+    }
+    pcs.sort_by_key(|(p, _)| sort_key(p));
+    (pcs.iter().map(|x| x.0).collect(), pcs.iter().map(|x| x.1).collect())
+}
+
+pub struct Solver {
+    pub memo: HashMap<Vec<Pc>, Rc<Solved>>,
+}
+
+impl Solver {
+    pub fn get(&mut self, mat: &[Pc]) -> Rc<Solved> {
+        if let Some(s) = self.memo.get(mat) {
+            return s.clone();
+        }
+        let s = Rc::new(self.solve(mat));
+        self.memo.insert(mat.to_vec(), s.clone());
+        s
+    }
+
+    /// value for the side to move in the child, after move m made by stm
+    fn out_value(&mut self, mat: &[Pc], sq: &[u8], stm: u8, m: &Mv) -> i8 {
+        let (cm, cs) = child(mat, sq, m);
+        let t = self.get(&cm);
+        let v = t.wdl[naive_idx(1 - stm, &cs)];
+        assert!(v != ILLEGAL, "illegal child {:?} {:?}", cm, cs);
+        v
+    }
+
+    fn solve(&mut self, mat: &[Pc]) -> Solved {
+        let n = mat.len();
+        assert!(n <= 4);
+        for i in 0..n {
+            for j in 0..i {
+                assert!(mat[i] != mat[j], "duplicate pieces unsupported");
+            }
+        }
+        let name: String = mat.iter().map(|p| format!("{}{}", if p.color == 0 { 'w' } else { 'b' }, "PNBRQK".as_bytes()[p.kind as usize] as char)).collect();
+        let t0 = std::time::Instant::now();
+        let size = 2 * 64usize.pow(n as u32);
+        let mut wdl = vec![ILLEGAL; size];
+        let mut counter = vec![0u8; size]; // in-table moves not yet known to lose
+        let mut can_lose = vec![false; size];
+        let mut nz_count = vec![0u8; size]; // non-zeroing moves
+        let mut mated = vec![false; size];
+        let mut dtz1 = vec![false; size]; // winning zeroing move or mating move
+        let mut queue: VecDeque<usize> = VecDeque::new();
+
+        // pass 1: legality and terminal / out-of-table results
+        for idx in 0..size {
+            let (stm, sqa) = decode(idx, n);
+            let sq = &sqa[..n];
+            if !legal_position(mat, sq, stm) {
+                continue;
+            }
+            let moves = gen_moves(mat, sq, stm);
+            wdl[idx] = 0;
+            if moves.is_empty() {
+                if in_check(mat, sq, &[true; 4][..n], stm) {
+                    wdl[idx] = -2;
+                    mated[idx] = true;
+                    queue.push_back(idx);
+                }
+                continue;
+            }
+            let mut win = false;
+            let mut lose_ok = true;
+            let mut cnt = 0u8;
+            let mut nz = 0u8;
+            for m in &moves {
+                if !m.zeroing(mat) {
+                    nz += 1;
+                }
+                if m.capture.is_some() || m.promo.is_some() {
+                    let v = -self.out_value(mat, sq, stm, m);
+                    if v == 2 {
+                        win = true;
+                        dtz1[idx] = true;
+                    } else if v == 0 {
+                        lose_ok = false;
+                    }
+                } else {
+                    cnt += 1;
+                }
+            }
+            counter[idx] = cnt;
+            nz_count[idx] = nz;
+            can_lose[idx] = lose_ok;
+            if win {
+                wdl[idx] = 2;
+                queue.push_back(idx);
+            } else if lose_ok && cnt == 0 {
+                wdl[idx] = -2;
+                queue.push_back(idx);
+            }
+        }
+
+        // pass 2: propagate through in-table moves (including pawn pushes)
+        let mut resolved = vec![false; size];
+        for &i in &queue {
+            resolved[i] = true;
+        }
+        while let Some(x) = queue.pop_front() {
+            let v = wdl[x];
+            for (y, _) in predecessors(mat, x, true) {
+                if resolved[y] {
+                    continue;
+                }
+                if v == -2 {
+                    wdl[y] = 2;
+                    resolved[y] = true;
+                    queue.push_back(y);
+                } else if v == 2 {
+                    counter[y] -= 1;
+                    if counter[y] == 0 && can_lose[y] {
+                        wdl[y] = -2;
+                        resolved[y] = true;
+                        queue.push_back(y);
+                    }
+                }
+            }
+        }
+
+        // pass 3: dtz. zeroing in-table moves (pawn pushes) and mates give dtz 1
+        let mut dtz = vec![0i16; size];
+        let mut nzc = nz_count.clone();
+        let mut q: VecDeque<usize> = VecDeque::new();
+        for idx in 0..size {
+            if wdl[idx] == 2 {
+                let mut one = dtz1[idx];
+                if !one {
+                    let (stm, sqa) = decode(idx, n);
+                    let sq = &sqa[..n];
+                    for m in gen_moves(mat, sq, stm) {
+                        if m.capture.is_some() || m.promo.is_some() {
+                            continue;
+                        }
+                        let mut s2 = sqa;
+                        s2[m.piece] = m.to;
+                        let c = naive_idx(1 - stm, &s2[..n]);
+                        if wdl[c] == -2 && (m.zeroing(mat) || mated[c]) {
+                            one = true;
+                            break;
+                        }
+                    }
+                }
+                if one {
+                    dtz[idx] = 1;
+                    q.push_back(idx);
+                }
+            } else if wdl[idx] == -2 && nz_count[idx] == 0 {
+                dtz[idx] = -1;
+                q.push_back(idx);
+            }
+        }
+        while let Some(x) = q.pop_front() {
+            let d = dtz[x];
+            for (y, zeroing) in predecessors(mat, x, false) {
+                assert!(!zeroing);
+                if dtz[y] != 0 {
+                    continue;
+                }
+                if d < 0 && wdl[y] == 2 {
+                    dtz[y] = -d + 1;
+                    q.push_back(y);
+                } else if d > 0 && wdl[y] == -2 {
+                    nzc[y] -= 1;
+                    if nzc[y] == 0 {
+                        dtz[y] = -(d + 1);
+                        q.push_back(y);
+                    }
+                }
+            }
+        }
+        let mut max_dtz = 0;
+        let (mut wins, mut losses, mut draws) = (0, 0, 0);
+        for idx in 0..size {
+            match wdl[idx] {
+                2 => wins += 1,
+                -2 => losses += 1,
+                0 => draws += 1,
+                _ => {}
+            }
+            if wdl[idx] == 2 || wdl[idx] == -2 {
+                assert!(dtz[idx] != 0, "{name}: unresolved dtz at {idx}");
+                assert_eq!(dtz[idx].signum() as i8, wdl[idx].signum());
+                max_dtz = max_dtz.max(dtz[idx].abs());
+            }
+        }
+        assert!(max_dtz <= 100, "{name}: cursed results not supported ({max_dtz})");
+        eprintln!("{name}: wins {wins} losses {losses} draws {draws} max dtz {max_dtz} ({:.1}s)", t0.elapsed().as_secs_f64());
+        Solved { wdl, dtz }
+    }
+}
+
+/// positions from which a non-capture, non-promotion move leads to `x`,
+/// with whether that move is zeroing (pawn push). Pawn pushes only if `pawns`.
+pub fn predecessors(mat: &[Pc], x: usize, pawns: bool) -> Vec<(usize, bool)> {
+    let n = mat.len();
+    let (stm, sqa) = decode(x, n);
+    let mover = 1 - stm;
+    let mut occ = 0u64;
+    for &s in &sqa[..n] {
+        occ |= 1 << s;
+    }
+    let mut out = Vec::new();
+    let push = |i: usize, t: u8, zeroing: bool, out: &mut Vec<(usize, bool)>| {
+        let mut s2 = sqa;
+        s2[i] = t;
+        // in the predecessor, `stm` is not to move so must not be in check
+        if !in_check(mat, &s2[..n], &[true; 4][..n], stm) {
+            out.push((naive_idx(mover, &s2[..n]), zeroing));
+        }
+    };
+    for i in 0..n {
+        let p = mat[i];
+        if p.color != mover {
+            continue;
+        }
+        let (f, r) = ((sqa[i] & 7) as i32, (sqa[i] >> 3) as i32);
+        if p.kind == P {
+            if !pawns {
+                continue;
+            }
+            let dir = if p.color == 0 { 1 } else { -1 };
+            let start = if p.color == 0 { 1 } else { 6 };
+            let back = r - dir;
+            if back == 0 || back == 7 {
+                continue;
+            }
+            let b1 = (back * 8 + f) as u8;
+            if occ & (1 << b1) != 0 {
+                continue;
+            }
+            push(i, b1, true, &mut out);
+            let back2 = r - 2 * dir;
+            if back2 == start {
+                let b2 = (back2 * 8 + f) as u8;
+                if occ & (1 << b2) == 0 {
+                    push(i, b2, true, &mut out);
+                }
+            }
+            continue;
+        }
+        let (ds, slide) = dirs(p.kind);
+        for &(df, dr) in ds {
+            let (mut cf, mut cr) = (f + df, r + dr);
+            while on(cf, cr) {
+                let t = (cr * 8 + cf) as u8;
+                if occ & (1 << t) != 0 {
+                    break;
+                }
+                push(i, t, false, &mut out);
+                if !slide {
+                    break;
+                }
+                cf += df;
+                cr += dr;
+            }
+        }
+    }
+    out
+}
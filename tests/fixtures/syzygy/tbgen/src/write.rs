@@ -0,0 +1,527 @@
+//! Writes tables in the Syzygy .rtbw / .rtbz layout.
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const BLOCK_BITS: u8 = 8; // 256 byte blocks
+const SPAN_BITS: u8 = 12;
+const MAX_SYMS: usize = 4095;
+
+// ---------------------------------------------------------------- indexing
+
+fn off_diag(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+fn binom(n: usize, k: usize) -> u64 {
+    if k > n {
+        return 0;
+    }
+    let mut r: u64 = 1;
+    for i in 0..k {
+        r = r * (n - i) as u64 / (i + 1) as u64;
+    }
+    r
+}
+
+/// a1-d1-d4 triangle squares to 0..9, off-diagonal ones first
+fn triangle(sq: usize) -> usize {
+    let off: Vec<usize> = (0..64).filter(|&s| s & 7 <= 3 && (s >> 3) <= 3 && off_diag(s) < 0).collect();
+    let diag: Vec<usize> = (0..64).filter(|&s| s & 7 <= 3 && off_diag(s) == 0).collect();
+    off.iter().chain(diag.iter()).position(|&s| s == sq).expect("not in triangle")
+}
+
+/// squares below the diagonal to 0..27
+fn below(sq: usize) -> usize {
+    (0..64).filter(|&s| off_diag(s) < 0).position(|s| s == sq).expect("not below diagonal")
+}
+
+/// pawn squares on files a-d, in order from a7 down, mirrored files interleaved (a2..h7 -> 0..47)
+fn pawn_map(sq: usize) -> usize {
+    let (f, r) = (sq & 7, sq >> 3);
+    if f <= 3 {
+        47 - 2 * (6 * f + r - 1)
+    } else {
+        46 - 2 * (6 * (7 - f) + r - 1)
+    }
+}
+
+pub struct Layout {
+    pub pawns: bool,
+    pub codes: Vec<u8>,        // syzygy piece codes in table order
+    pub group_len: Vec<usize>, // groups, the first being the leading group
+    pub group_mul: Vec<u64>,   // multiplier of each group
+    pub size: u64,
+}
+
+/// groups in the order the table stores them, with the leading group least significant
+pub fn layout(codes: &[u8], pawns: bool, file: usize) -> Layout {
+    let n = codes.len();
+    let unique = codes.iter().any(|&c| c & 7 != 6 && codes.iter().filter(|&&d| d == c).count() == 1);
+    let mut group_len: Vec<usize> = Vec::new();
+    let first = if pawns { 1 } else if unique { 3 } else { 2 };
+    // leading group: for pawns all pawns of the leading colour (consecutive equal codes)
+    let mut i = if pawns {
+        let mut j = 1;
+        while j < n && codes[j] == codes[0] {
+            j += 1;
+        }
+        j
+    } else {
+        first
+    };
+    group_len.push(i);
+    while i < n {
+        let mut j = i + 1;
+        while j < n && codes[j] == codes[i] {
+            j += 1;
+        }
+        group_len.push(j - i);
+        i = j;
+    }
+    let mut group_mul = Vec::new();
+    let mut mul: u64 = 1;
+    let mut used = 0;
+    for (g, &len) in group_len.iter().enumerate() {
+        group_mul.push(mul);
+        let count = if g == 0 {
+            if pawns {
+                (1..7).map(|r| binom(pawn_map(8 * r + file), len - 1)).sum::<u64>()
+            } else if unique {
+                31332
+            } else {
+                462
+            }
+        } else {
+            binom(64 - used, len)
+        };
+        used += len;
+        mul *= count;
+    }
+    Layout { pawns, codes: codes.to_vec(), group_len, group_mul, size: mul }
+}
+
+fn kk_index(s0: usize, s1: usize) -> u64 {
+    // enumerate pairs as the format does: triangle square of the first king, then the second king
+    let mut kk = 0u64;
+    let mut diag_pairs: Vec<(usize, usize)> = Vec::new();
+    for idx in 0..10 {
+        for a in 0..64 {
+            if a & 7 > 3 || a >> 3 > 3 || off_diag(a) > 0 || triangle(a) != idx {
+                continue;
+            }
+            if idx == 0 && a != 1 {
+                continue;
+            }
+            for b in 0..64 {
+                let adjacent = ((a & 7) as i32 - (b & 7) as i32).abs() <= 1 && ((a >> 3) as i32 - (b >> 3) as i32).abs() <= 1;
+                if adjacent || (off_diag(a) == 0 && off_diag(b) > 0) {
+                    continue;
+                }
+                if off_diag(a) == 0 && off_diag(b) == 0 {
+                    diag_pairs.push((a, b));
+                    continue;
+                }
+                if (a, b) == (s0, s1) {
+                    return kk;
+                }
+                kk += 1;
+            }
+        }
+    }
+    for (a, b) in diag_pairs {
+        if (a, b) == (s0, s1) {
+            return kk;
+        }
+        kk += 1;
+    }
+    panic!("king pair not found")
+}
+
+/// index of a position given as (code, square) in table orientation, already mirrored so
+/// the leading piece is in the canonical region.
+pub fn encode(lay: &Layout, pieces: &[(u8, usize)]) -> u64 {
+    // order pieces as in the table
+    let mut sq: Vec<usize> = Vec::new();
+    let mut taken = vec![false; pieces.len()];
+    for &c in &lay.codes {
+        let j = (0..pieces.len()).find(|&j| !taken[j] && pieces[j].0 == c).unwrap();
+        taken[j] = true;
+        sq.push(pieces[j].1);
+    }
+    let n = sq.len();
+    let mut idx: u64;
+    let g0 = lay.group_len[0];
+    if lay.pawns {
+        // leading pawn first: the one with the largest pawn_map value
+        let lead = (0..g0).max_by_key(|&i| pawn_map(sq[i])).unwrap();
+        sq.swap(0, lead);
+        if sq[0] & 7 > 3 {
+            sq.iter_mut().for_each(|s| *s ^= 7);
+        }
+        let file = sq[0] & 7;
+        idx = (1..7).take_while(|&r| 8 * r + file != sq[0]).map(|r| binom(pawn_map(8 * r + file), g0 - 1)).sum();
+        let mut rest: Vec<usize> = sq[1..g0].to_vec();
+        rest.sort_by_key(|&s| pawn_map(s));
+        for (i, &s) in rest.iter().enumerate() {
+            idx += binom(pawn_map(s), i + 1);
+        }
+    } else {
+        if sq[0] & 7 > 3 {
+            sq.iter_mut().for_each(|s| *s ^= 7);
+        }
+        if sq[0] >> 3 > 3 {
+            sq.iter_mut().for_each(|s| *s ^= 56);
+        }
+        for i in 0..g0 {
+            let off = off_diag(sq[i]);
+            if off == 0 {
+                continue;
+            }
+            if off > 0 {
+                sq.iter_mut().skip(i).for_each(|s| *s = ((*s >> 3) | (*s << 3)) & 63);
+            }
+            break;
+        }
+        let (s0, s1) = (sq[0], sq[1]);
+        if g0 == 3 {
+            let s2 = sq[2];
+            let a1 = usize::from(s1 > s0);
+            let a2 = usize::from(s2 > s0) + usize::from(s2 > s1);
+            idx = if off_diag(s0) != 0 {
+                ((triangle(s0) * 63 + s1 - a1) * 62 + s2 - a2) as u64
+            } else if off_diag(s1) != 0 {
+                ((6 * 63 + (s0 >> 3) * 28 + below(s1)) * 62 + s2 - a2) as u64
+            } else if off_diag(s2) != 0 {
+                (6 * 63 * 62 + 4 * 28 * 62 + (s0 >> 3) * 7 * 28 + ((s1 >> 3) - a1) * 28 + below(s2)) as u64
+            } else {
+                (6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + (s0 >> 3) * 7 * 6 + ((s1 >> 3) - a1) * 6 + (s2 >> 3) - a2) as u64
+            };
+        } else {
+            idx = kk_index(s0, s1);
+        }
+    }
+    idx *= lay.group_mul[0];
+    let mut start = g0;
+    for g in 1..lay.group_len.len() {
+        let len = lay.group_len[g];
+        let mut grp: Vec<usize> = sq[start..start + len].to_vec();
+        grp.sort();
+        let mut v = 0u64;
+        for (i, &s) in grp.iter().enumerate() {
+            let adjust = sq[..start].iter().filter(|&&o| s > o).count();
+            v += binom(s - adjust, i + 1);
+        }
+        idx += v * lay.group_mul[g];
+        start += len;
+    }
+    assert!(n == start);
+    idx
+}
+
+// ---------------------------------------------------------------- compression
+
+pub struct Pairs {
+    single: Option<u16>,
+    flags: u8,
+    min_len: u8,
+    max_len: u8,
+    lowest: Vec<u16>,
+    btree: Vec<(u16, u16)>,
+    blocks: Vec<Vec<u8>>,
+    block_lens: Vec<u16>,
+    padding: u8,
+    sparse: Vec<(u32, u16)>,
+}
+
+fn huffman_lengths(freq: &[u64]) -> Vec<u8> {
+    let n = freq.len();
+    let mut f: Vec<u64> = freq.iter().map(|&x| x.max(1)).collect();
+    loop {
+        // (weight, node); nodes >= n are internal
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = f.iter().enumerate().map(|(i, &w)| Reverse((w, i))).collect();
+        let mut parent = vec![usize::MAX; 2 * n];
+        let mut next = n;
+        while heap.len() > 1 {
+            let Reverse((w1, a)) = heap.pop().unwrap();
+            let Reverse((w2, b)) = heap.pop().unwrap();
+            parent[a] = next;
+            parent[b] = next;
+            heap.push(Reverse((w1 + w2, next)));
+            next += 1;
+        }
+        let mut lens = vec![0u8; n];
+        for (i, l) in lens.iter_mut().enumerate() {
+            let mut x = i;
+            while parent[x] != usize::MAX {
+                x = parent[x];
+                *l += 1;
+            }
+        }
+        if *lens.iter().max().unwrap() <= 24 {
+            return lens;
+        }
+        f.iter_mut().for_each(|x| *x = (*x).div_ceil(2));
+    }
+}
+
+/// Re-Pair style grammar: returns (btree of (left, right) with right == 0xFFF for leaves, stream)
+fn repair(values: &[u16]) -> (Vec<(u16, u16)>, Vec<u16>, Vec<u32>) {
+    let mut leaves: Vec<u16> = values.to_vec();
+    leaves.sort();
+    leaves.dedup();
+    let mut btree: Vec<(u16, u16)> = leaves.iter().map(|&v| (v, 0xFFF)).collect();
+    let mut expand: Vec<u32> = vec![1; btree.len()];
+    let pos: HashMap<u16, u16> = leaves.iter().enumerate().map(|(i, &v)| (v, i as u16)).collect();
+    let mut stream: Vec<u16> = values.iter().map(|v| pos[v]).collect();
+    let mut counts: Vec<u32> = vec![0; 4096 * 4096];
+    loop {
+        if btree.len() >= MAX_SYMS {
+            break;
+        }
+        counts.iter_mut().for_each(|c| *c = 0);
+        let mut i = 0;
+        while i + 1 < stream.len() {
+            let key = stream[i] as usize * 4096 + stream[i + 1] as usize;
+            counts[key] += 1;
+            // avoid counting overlapping runs twice
+            if stream[i] == stream[i + 1] && i + 2 < stream.len() && stream[i + 2] == stream[i] {
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+        let mut cands: Vec<(u32, usize)> = counts.iter().enumerate().filter(|(_, &c)| c >= 8).map(|(k, &c)| (c, k)).collect();
+        if cands.is_empty() {
+            break;
+        }
+        cands.sort_unstable_by(|a, b| b.cmp(a));
+        let mut chosen: HashMap<(u16, u16), u16> = HashMap::new();
+        let mut used: Vec<bool> = vec![false; 4096];
+        for (_, k) in cands {
+            if btree.len() >= MAX_SYMS || chosen.len() >= 64 {
+                break;
+            }
+            let (a, b) = ((k / 4096) as u16, (k % 4096) as u16);
+            if used[a as usize] || used[b as usize] {
+                continue;
+            }
+            if expand[a as usize] + expand[b as usize] > 256 {
+                continue;
+            }
+            used[a as usize] = true;
+            used[b as usize] = true;
+            chosen.insert((a, b), btree.len() as u16);
+            expand.push(expand[a as usize] + expand[b as usize]);
+            btree.push((a, b));
+        }
+        if chosen.is_empty() {
+            break;
+        }
+        let mut out: Vec<u16> = Vec::with_capacity(stream.len());
+        let mut i = 0;
+        while i < stream.len() {
+            if i + 1 < stream.len() {
+                if let Some(&s) = chosen.get(&(stream[i], stream[i + 1])) {
+                    out.push(s);
+                    i += 2;
+                    continue;
+                }
+            }
+            out.push(stream[i]);
+            i += 1;
+        }
+        stream = out;
+    }
+    (btree, stream, expand)
+}
+
+pub fn compress(values: &[u16], flags: u8) -> Pairs {
+    let first = values[0];
+    if values.iter().all(|&v| v == first) {
+        return Pairs {
+            single: Some(first), flags, min_len: 0, max_len: 0, lowest: vec![], btree: vec![], blocks: vec![],
+            block_lens: vec![], padding: 0, sparse: vec![],
+        };
+    }
+    let (btree, stream, expand) = repair(values);
+    let nsym = btree.len();
+    let mut freq = vec![0u64; nsym];
+    for &s in &stream {
+        freq[s as usize] += 1;
+    }
+    let lens = huffman_lengths(&freq);
+    let min_len = *lens.iter().min().unwrap();
+    let max_len = *lens.iter().max().unwrap();
+
+    // renumber: longest codes get the lowest symbol numbers
+    let mut order: Vec<usize> = (0..nsym).collect();
+    order.sort_by_key(|&s| (Reverse(lens[s]), s));
+    let mut renum = vec![0u16; nsym];
+    for (new, &old) in order.iter().enumerate() {
+        renum[old] = new as u16;
+    }
+    let new_btree: Vec<(u16, u16)> = order
+        .iter()
+        .map(|&old| {
+            let (l, r) = btree[old];
+            if r == 0xFFF { (l, r) } else { (renum[l as usize], renum[r as usize]) }
+        })
+        .collect();
+    let new_lens: Vec<u8> = order.iter().map(|&old| lens[old]).collect();
+    let new_expand: Vec<u32> = order.iter().map(|&old| expand[old]).collect();
+    let stream: Vec<u16> = stream.iter().map(|&s| renum[s as usize]).collect();
+
+    let nl = (max_len - min_len + 1) as usize;
+    let mut count = vec![0u64; nl];
+    for &l in &new_lens {
+        count[(l - min_len) as usize] += 1;
+    }
+    let mut lowest = vec![0u16; nl];
+    let mut base = vec![0u64; nl];
+    for i in (0..nl - 1).rev() {
+        lowest[i] = lowest[i + 1] + count[i + 1] as u16;
+        assert!((base[i + 1] + count[i + 1]).is_multiple_of(2));
+        base[i] = (base[i + 1] + count[i + 1]) / 2;
+    }
+    assert_eq!(base[0] + count[0], 1 << min_len, "code not complete");
+    let code = |s: u16| -> (u64, u8) {
+        let l = new_lens[s as usize];
+        let i = (l - min_len) as usize;
+        (base[i] + (s - lowest[i]) as u64, l)
+    };
+
+    // pack into blocks
+    let block_size = 1usize << BLOCK_BITS;
+    let budget = block_size * 8 - 64;
+    let mut blocks: Vec<Vec<u8>> = Vec::new();
+    let mut block_lens: Vec<u16> = Vec::new();
+    let mut block_starts: Vec<u64> = Vec::new();
+    let mut i = 0;
+    let mut vstart = 0u64;
+    while i < stream.len() {
+        let mut bits: Vec<bool> = Vec::new();
+        let mut vals: u64 = 0;
+        while i < stream.len() {
+            let (c, l) = code(stream[i]);
+            let e = new_expand[stream[i] as usize] as u64;
+            if bits.len() + l as usize > budget || vals + e > 65536 {
+                break;
+            }
+            for b in (0..l).rev() {
+                bits.push((c >> b) & 1 == 1);
+            }
+            vals += e;
+            i += 1;
+        }
+        assert!(vals > 0);
+        let mut bytes = vec![0u8; block_size];
+        for (k, &b) in bits.iter().enumerate() {
+            if b {
+                bytes[k / 8] |= 0x80 >> (k % 8);
+            }
+        }
+        blocks.push(bytes);
+        block_lens.push((vals - 1) as u16);
+        block_starts.push(vstart);
+        vstart += vals;
+    }
+    let total = values.len() as u64;
+    assert_eq!(vstart, total);
+
+    let span = 1u64 << SPAN_BITS;
+    let nsparse = total.div_ceil(span);
+    let mut sparse = Vec::new();
+    let mut padding = 0u64;
+    for k in 0..nsparse {
+        let r = k * span + span / 2;
+        if r < total {
+            let b = block_starts.partition_point(|&s| s <= r) - 1;
+            sparse.push((b as u32, (r - block_starts[b]) as u16));
+        } else {
+            let extra = (r - total) / 65536;
+            padding = padding.max(extra + 1);
+            sparse.push(((blocks.len() as u64 + extra) as u32, ((r - total) % 65536) as u16));
+        }
+    }
+    block_lens.extend(std::iter::repeat_n(65535, padding as usize));
+    Pairs {
+        single: None, flags, min_len, max_len, lowest, btree: new_btree, blocks, block_lens, padding: padding as u8, sparse,
+    }
+}
+
+impl Pairs {
+    fn header(&self) -> Vec<u8> {
+        if let Some(v) = self.single {
+            return vec![self.flags | 0x80, v as u8];
+        }
+        let mut h = vec![self.flags, BLOCK_BITS, SPAN_BITS, self.padding];
+        h.extend_from_slice(&(self.blocks.len() as u32).to_le_bytes());
+        h.push(self.max_len);
+        h.push(self.min_len);
+        for &l in &self.lowest {
+            h.extend_from_slice(&l.to_le_bytes());
+        }
+        h.extend_from_slice(&(self.btree.len() as u16).to_le_bytes());
+        for &(l, r) in &self.btree {
+            h.push((l & 0xFF) as u8);
+            h.push(((l >> 8) as u8 & 0xF) | (((r & 0xF) as u8) << 4));
+            h.push((r >> 4) as u8);
+        }
+        if self.btree.len() & 1 == 1 {
+            h.push(0);
+        }
+        h
+    }
+}
+
+/// items[file][side]
+pub fn write_file(path: &str, dtz: bool, pawns: bool, codes: &[[u8; 2]], items: &[Vec<Pairs>]) {
+    let mut out: Vec<u8> = Vec::new();
+    out.extend_from_slice(if dtz { &DTZ_MAGIC } else { &WDL_MAGIC });
+    let sides = items[0].len();
+    out.push(u8::from(sides == 2) | (u8::from(pawns) << 1));
+    for _ in items {
+        // order: leading group first
+        out.extend(std::iter::once(0).chain(codes.iter().map(|c| c[0] | (c[1] << 4))));
+    }
+    if out.len() & 1 == 1 {
+        out.push(0);
+    }
+    for f in items {
+        for p in f {
+            out.extend(p.header());
+        }
+    }
+    if dtz && out.len() & 1 == 1 {
+        out.push(0);
+    }
+    for f in items {
+        for p in f {
+            for &(b, o) in &p.sparse {
+                out.extend_from_slice(&b.to_le_bytes());
+                out.extend_from_slice(&o.to_le_bytes());
+            }
+        }
+    }
+    for f in items {
+        for p in f {
+            for &l in &p.block_lens {
+                out.extend_from_slice(&l.to_le_bytes());
+            }
+        }
+    }
+    for f in items {
+        for p in f {
+            while !out.len().is_multiple_of(64) {
+                out.push(0);
+            }
+            for b in &p.blocks {
+                out.extend_from_slice(b);
+            }
+        }
+    }
+    std::fs::write(path, &out).unwrap();
+    eprintln!("wrote {path} ({} bytes)", out.len());
+}